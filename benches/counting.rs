@@ -1,6 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use interp_test::imsta::*;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use interp_test::jit::JitProgram;
 use interp_test::expr::*;
+use interp_test::passes::check_types;
 
 fn count_native() {
     let mut i = black_box(10_000_000);
@@ -10,8 +13,8 @@ fn count_native() {
     }
 }
 
-fn count_tape() {
-    let expr = Expr::Block(vec![
+fn count_program() -> Expr {
+    Expr::Block(vec![
         Binding::Global("x".into()).assign(Expr::Float(10_000_000.0)),
         Expr::While(
             Expr::BinaryOp(
@@ -28,9 +31,26 @@ fn count_tape() {
                 ),
             ).into(),
         ),
-    ]);
+    ])
+}
 
+fn count_tape() {
     let mut compiler = ImCompiler::new();
+    compiler.compile_expr(count_program());
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    black_box(context.execute());
+}
+
+fn count_specialized() {
+    let expr = count_program();
+
+    let mut compiler = ImCompiler::with_types(check_types(&expr).unwrap());
     compiler.compile_expr(expr);
 
     let mut context = CallContext::new(
@@ -42,9 +62,23 @@ fn count_tape() {
     black_box(context.execute());
 }
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn count_jit() {
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(count_program());
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut context = jit.context();
+
+    black_box(jit.execute(&mut context));
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("count_native(10M)", |b| b.iter(|| black_box(count_native())));
     c.bench_function("count(10M)", |b| b.iter(|| black_box(count_tape())));
+    c.bench_function("count_specialized(10M)", |b| b.iter(|| black_box(count_specialized())));
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    c.bench_function("count_jit(10M)", |b| b.iter(|| black_box(count_jit())));
 }
 
 criterion_group!(benches, criterion_benchmark);
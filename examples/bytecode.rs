@@ -0,0 +1,60 @@
+//! Compiles a program once and runs it from its bytecode file:
+//!
+//! ```text
+//! cargo run --example bytecode -- save counting.ista
+//! cargo run --example bytecode -- run counting.ista
+//! ```
+
+use std::process::ExitCode;
+
+use interp_test::expr::*;
+use interp_test::imsta::*;
+
+fn count_program() -> Expr {
+    let x = || Binding::Global("x".into());
+
+    Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(1000.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+    ])
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["save", path] => {
+            let mut compiler = ImCompiler::new();
+            compiler.compile_expr(count_program());
+            compiler.save(path)
+        }
+        ["run", path] => ImCompiler::load(path).map(|compiler| {
+            let mut context = CallContext::new(
+                compiler.future_tape.as_ptr(),
+                compiler.future_tape.len(),
+                compiler.globals.len(),
+            );
+            println!("{:?}", context.execute());
+
+            for (name, value) in compiler.globals.iter().zip(&context.globals) {
+                println!("{name} = {value:?}");
+            }
+        }),
+        _ => {
+            eprintln!("usage: bytecode (save|run) <path>");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
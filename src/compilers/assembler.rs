@@ -0,0 +1,402 @@
+//! A textual assembly language for tapes.
+//!
+//! Every line holds one instruction, its mnemonic being the
+//! [name](OpCode::name) of the operation followed by its immediate
+//! [operands](OpCode::operands) separated by commas. The instructions an
+//! operation evaluates follow it on the next lines:
+//!
+//! ```text
+//! .global x
+//!
+//!     block_checked end
+//!     assign x
+//!     float 0.0
+//!     hint while
+//!     while_loop end
+//!     lt
+//!     var x
+//!     float 10.0
+//!     assign x
+//!     add
+//!     var x
+//!     float 1.0
+//! end:
+//! ```
+//!
+//! - jump targets are labels (`name:`, before an instruction or on their
+//!   own line) or absolute offsets
+//! - `hint return|break|while` emits a hint, `branch <target>` the false
+//!   jump starting a `conditional` branch and a bare integer a raw cell
+//! - `.global name` declares the next global, globals used without being
+//!   declared are declared on first use and `#3` refers to a global by index
+//! - `;` starts a comment
+//!
+//! The [raw listing](Dissassembler::listing) is accepted as is: leading
+//! offsets are checked against the assembled ones, `->` before jump
+//! targets and the `(...)` note after them are ignored.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based line of the error.
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    UnknownHint(String),
+    WrongOperandCount { expected: usize, found: usize },
+    InvalidOperand(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    OffsetMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic `{name}`"),
+            AsmErrorKind::UnknownHint(name) => write!(f, "unknown hint `{name}`"),
+            AsmErrorKind::WrongOperandCount { expected, found } => {
+                write!(f, "expected {expected} operands, found {found}")
+            }
+            AsmErrorKind::InvalidOperand(operand) => write!(f, "invalid operand `{operand}`"),
+            AsmErrorKind::UnknownLabel(label) => write!(f, "unknown label `{label}`"),
+            AsmErrorKind::DuplicateLabel(label) => write!(f, "label `{label}` is already defined"),
+            AsmErrorKind::OffsetMismatch { expected, found } => {
+                write!(f, "instruction is at offset {expected}, not {found}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into a compiler holding the tape and its globals.
+///
+/// Nothing is checked past the syntax, run [`ImCompiler::verify`] before
+/// executing hand-written tapes.
+pub fn assemble(source: &str) -> Result<ImCompiler, AsmError> {
+    let mut assembler = Assembler {
+        compiler: ImCompiler::new(),
+        labels: HashMap::new(),
+        fixups: Vec::new(),
+        line: 0,
+    };
+
+    for (idx, line) in source.lines().enumerate() {
+        assembler.line = idx + 1;
+        assembler.line(line).map_err(|kind| assembler.error(kind))?;
+    }
+
+    for (line, offset, label) in std::mem::take(&mut assembler.fixups) {
+        let target = *assembler.labels.get(&label).ok_or(AsmError {
+            line,
+            kind: AsmErrorKind::UnknownLabel(label),
+        })?;
+
+        assembler.compiler.future_tape[offset] = target as u64;
+    }
+
+    Ok(assembler.compiler)
+}
+
+struct Assembler {
+    compiler: ImCompiler,
+    labels: HashMap<String, usize>,
+    /// Jump cells waiting for a label's offset, with their line.
+    fixups: Vec<(usize, usize, String)>,
+    line: usize,
+}
+
+impl Assembler {
+    fn error(&self, kind: AsmErrorKind) -> AsmError {
+        AsmError {
+            line: self.line,
+            kind,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.compiler.future_tape.len()
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), AsmErrorKind> {
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+
+        while let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+
+            if !is_identifier(label) {
+                break;
+            }
+
+            if self.labels.insert(label.into(), self.offset()).is_some() {
+                return Err(AsmErrorKind::DuplicateLabel(label.into()));
+            }
+
+            line = rest.trim();
+        }
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mut mnemonic, mut rest) = split_word(line);
+
+        // Listings start every line with the instruction's offset.
+        if let (Some(found), false) = (parse_int(mnemonic), rest.is_empty()) {
+            let expected = self.offset();
+
+            if found as usize != expected {
+                return Err(AsmErrorKind::OffsetMismatch {
+                    expected,
+                    found: found as usize,
+                });
+            }
+
+            (mnemonic, rest) = split_word(rest);
+        }
+
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+
+        let expect = |expected: usize| {
+            if operands.len() == expected {
+                Ok(())
+            } else {
+                Err(AsmErrorKind::WrongOperandCount {
+                    expected,
+                    found: operands.len(),
+                })
+            }
+        };
+
+        if let Some(cell) = parse_int(mnemonic) {
+            expect(0)?;
+            self.compiler.push(cell);
+            return Ok(());
+        }
+
+        match mnemonic {
+            ".global" => {
+                expect(1)?;
+                self.compiler.globals.push(operands[0].into());
+            }
+            "hint" => {
+                expect(1)?;
+
+                let hint = [Hint::Return, Hint::Break, Hint::While]
+                    .into_iter()
+                    .find(|hint| format!("{hint:?}").eq_ignore_ascii_case(operands[0]))
+                    .ok_or_else(|| AsmErrorKind::UnknownHint(operands[0].into()))?;
+
+                self.compiler.push(hint.cell());
+            }
+            "branch" => {
+                expect(1)?;
+                self.jump(operands[0])?;
+            }
+            _ => {
+                let op = OpCode::from_name(mnemonic)
+                    .ok_or_else(|| AsmErrorKind::UnknownMnemonic(mnemonic.into()))?;
+
+                let immediates: Vec<&Operand> = op
+                    .operands()
+                    .iter()
+                    .filter(|operand| {
+                        matches!(
+                            operand,
+                            Operand::Float | Operand::Global | Operand::Jump | Operand::Count
+                        )
+                    })
+                    .collect();
+                expect(immediates.len())?;
+
+                self.compiler.push(op.address());
+
+                for (operand, text) in immediates.into_iter().zip(operands) {
+                    let invalid = || AsmErrorKind::InvalidOperand(text.into());
+
+                    match operand {
+                        Operand::Float => {
+                            let x: f64 = text.parse().map_err(|_| invalid())?;
+                            self.compiler.push(x.to_bits());
+                        }
+                        Operand::Global => {
+                            let idx = match text.strip_prefix('#') {
+                                Some(idx) => idx.parse().map_err(|_| invalid())?,
+                                None if is_identifier(text) => {
+                                    self.compiler.constant_get_or_def(text) as u64
+                                }
+                                None => return Err(invalid()),
+                            };
+
+                            self.compiler.push(idx);
+                        }
+                        Operand::Count => {
+                            let count = parse_int(text).ok_or_else(invalid)?;
+                            self.compiler.push(count);
+                        }
+                        _ => self.jump(text)?,
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a jump cell, written as a label or an absolute offset.
+    fn jump(&mut self, text: &str) -> Result<(), AsmErrorKind> {
+        let mut target = text.strip_prefix("->").unwrap_or(text).trim();
+
+        if let Some(idx) = target.find('(') {
+            target = target[..idx].trim();
+        }
+
+        if let Some(offset) = parse_int(target) {
+            self.compiler.push(offset);
+        } else if is_identifier(target) {
+            self.fixups.push((self.line, self.offset(), target.into()));
+            self.compiler.push(0);
+        } else {
+            return Err(AsmErrorKind::InvalidOperand(text.into()));
+        }
+
+        Ok(())
+    }
+}
+
+fn split_word(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+fn parse_int(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[test]
+pub fn assembler() {
+    use crate::expr::*;
+
+    let compiler = assemble(
+        "
+        .global x
+        .global y
+
+            block_checked end       ; y = 0; while x < 10 { x = x + 1 }
+            assign y
+            float 0.0
+            hint while
+            while_loop end
+            lt
+            var x
+            float 10.0
+            assign x
+            add
+            var #0
+            float 1.0
+        end:
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(compiler.globals, vec!["x", "y"]);
+    assert_eq!(compiler.verify(), Ok(()));
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.execute();
+
+    assert_eq!(context.globals, vec![Value::Float(10.0), Value::Float(0.0)]);
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(3.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Expr::Conditional(
+            (
+                x().var().op(Operator::Eq, Expr::Float(3.0)),
+                Expr::Return(Expr::Boolean(true).into()),
+            )
+                .into(),
+            vec![(Expr::Boolean(false), Expr::Float(f64::NEG_INFINITY))],
+            None,
+        ),
+    ]);
+
+    let mut compiled = ImCompiler::new();
+    compiled.compile_expr(program);
+
+    let listing = Dissassembler::from(compiled.clone()).listing().to_string();
+    let assembled = assemble(&listing).unwrap();
+
+    assert_eq!(assembled.future_tape, compiled.future_tape);
+    assert_eq!(assembled.globals, compiled.globals);
+
+    for (source, kind) in [
+        ("jump 3", AsmErrorKind::UnknownMnemonic("jump".into())),
+        ("hint loop", AsmErrorKind::UnknownHint("loop".into())),
+        (
+            "block",
+            AsmErrorKind::WrongOperandCount {
+                expected: 1,
+                found: 0,
+            },
+        ),
+        ("float one", AsmErrorKind::InvalidOperand("one".into())),
+        ("a:\na: true", AsmErrorKind::DuplicateLabel("a".into())),
+        (
+            "true\n    4  false",
+            AsmErrorKind::OffsetMismatch {
+                expected: 1,
+                found: 4,
+            },
+        ),
+    ] {
+        assert_eq!(assemble(source).unwrap_err().kind, kind);
+    }
+
+    assert_eq!(
+        assemble("block missing").unwrap_err(),
+        AsmError {
+            line: 1,
+            kind: AsmErrorKind::UnknownLabel("missing".into())
+        }
+    );
+}
@@ -0,0 +1,337 @@
+//! Versioned on-disk format for compiled programs.
+//!
+//! All integers are little endian:
+//!
+//! ```text
+//! magic      b"ISTA"
+//! version    u32
+//! globals    u32 count, then per global a u32 length and UTF-8 name
+//! constants  u32 count, then the f64 bits of every constant
+//! tape       u32 count, then every cell as u64, see below
+//! checksum   u64 FNV-1a hash of every byte before it
+//! ```
+//!
+//! The tape is [encoded](ImCompiler::encode) with stable operation ids and
+//! the immediate of every `float` and `raw_float` is an index into the
+//! constant section.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::*;
+
+pub const MAGIC: [u8; 4] = *b"ISTA";
+pub const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    BadChecksum,
+    TrailingBytes,
+    InvalidName,
+    BadConstant { offset: usize, index: u64 },
+    Decode(DecodeError),
+    Invalid(VerifyError),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::Io(err) => write!(f, "{err}"),
+            BytecodeError::BadMagic => write!(f, "not a bytecode file"),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported bytecode version {version}, expected {VERSION}"
+                )
+            }
+            BytecodeError::Truncated => write!(f, "unexpected end of bytecode"),
+            BytecodeError::BadChecksum => write!(f, "checksum mismatch"),
+            BytecodeError::TrailingBytes => write!(f, "unexpected data after the checksum"),
+            BytecodeError::InvalidName => write!(f, "global name is not valid UTF-8"),
+            BytecodeError::BadConstant { offset, index } => {
+                write!(f, "unknown constant {index} at offset {offset}")
+            }
+            BytecodeError::Decode(err) => write!(f, "{err}"),
+            BytecodeError::Invalid(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl From<io::Error> for BytecodeError {
+    fn from(err: io::Error) -> Self {
+        BytecodeError::Io(err)
+    }
+}
+
+impl From<DecodeError> for BytecodeError {
+    fn from(err: DecodeError) -> Self {
+        BytecodeError::Decode(err)
+    }
+}
+
+impl ImCompiler {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DecodeError> {
+        let program = Node::decode(&self.future_tape)?;
+
+        let mut tape = Vec::with_capacity(self.future_tape.len());
+        program.emit_with(&mut tape, OpCode::id);
+
+        let mut constants: Vec<u64> = Vec::new();
+        let mut immediates = Vec::new();
+        collect_immediates(&program, &mut immediates);
+
+        for offset in immediates {
+            let bits = tape[offset];
+
+            tape[offset] = match constants.iter().position(|constant| *constant == bits) {
+                Some(index) => index as u64,
+                None => {
+                    constants.push(bits);
+                    constants.len() as u64 - 1
+                }
+            };
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+
+        bytes.extend((self.globals.len() as u32).to_le_bytes());
+        for name in &self.globals {
+            bytes.extend((name.len() as u32).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+
+        for section in [&constants, &tape] {
+            bytes.extend((section.len() as u32).to_le_bytes());
+            for cell in section {
+                bytes.extend(cell.to_le_bytes());
+            }
+        }
+
+        bytes.extend(checksum(&bytes).to_le_bytes());
+
+        Ok(bytes)
+    }
+
+    /// Loads a program saved by [`ImCompiler::to_bytes`], which is
+    /// [verified](ImCompiler::verify) before being returned.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ImCompiler, BytecodeError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+
+        let mut reader = Reader {
+            bytes,
+            offset: MAGIC.len(),
+        };
+
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+
+        let globals = (0..reader.u32()?)
+            .map(|_| {
+                let len = reader.u32()? as usize;
+                String::from_utf8(reader.take(len)?.to_vec())
+                    .map_err(|_| BytecodeError::InvalidName)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let constants = reader.section()?;
+        let mut tape = reader.section()?;
+
+        let hashed = reader.offset;
+        if checksum(&bytes[..hashed]) != reader.u64()? {
+            return Err(BytecodeError::BadChecksum);
+        }
+        if reader.offset != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        let program = Node::decode_with(&tape, OpCode::from_id)?;
+
+        let mut immediates = Vec::new();
+        collect_immediates(&program, &mut immediates);
+
+        for offset in immediates {
+            let index = tape[offset];
+
+            tape[offset] = *constants
+                .get(index as usize)
+                .ok_or(BytecodeError::BadConstant { offset, index })?;
+        }
+
+        let compiler = ImCompiler::link(globals, &tape)?;
+        compiler.verify().map_err(BytecodeError::Invalid)?;
+
+        Ok(compiler)
+    }
+
+    /// Writes the program to `path` in the [bytecode](crate::bytecode) format.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BytecodeError> {
+        std::fs::write(path, self.to_bytes()?)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<ImCompiler, BytecodeError> {
+        ImCompiler::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Offsets of the cells holding float immediates.
+fn collect_immediates(node: &Node, offsets: &mut Vec<usize>) {
+    if let NodeKind::Float(_) | NodeKind::RawFloat(_) = node.kind {
+        offsets.push(node.offset + 1);
+    }
+
+    for child in node.children() {
+        collect_immediates(child, offsets);
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or(BytecodeError::Truncated)?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn section(&mut self) -> Result<Vec<u64>, BytecodeError> {
+        (0..self.u32()?).map(|_| self.u64()).collect()
+    }
+}
+
+#[test]
+pub fn bytecode() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.5)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(0.5)))
+                .into(),
+        ),
+        Binding::Global("y".into()).assign(x().var().op(Operator::Mul, Expr::Float(10.0))),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let bytes = compiler.to_bytes().unwrap();
+    let path = std::env::temp_dir().join(format!("bytecode-{}.ista", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+
+    let loaded = ImCompiler::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.globals, compiler.globals);
+    assert_eq!(loaded.future_tape, compiler.future_tape);
+
+    let mut context = CallContext::new(
+        loaded.future_tape.as_ptr(),
+        loaded.future_tape.len(),
+        loaded.globals.len(),
+    );
+    context.execute();
+
+    assert_eq!(
+        context.globals,
+        vec![Value::Float(10.0), Value::Float(100.0)]
+    );
+
+    let mut corrupted = bytes.clone();
+    let last_cell = corrupted.len() - 16;
+    corrupted[last_cell] ^= 1;
+    assert!(matches!(
+        ImCompiler::from_bytes(&corrupted),
+        Err(BytecodeError::BadChecksum)
+    ));
+
+    let mut future = bytes.clone();
+    future[4] = 3;
+    assert!(matches!(
+        ImCompiler::from_bytes(&future),
+        Err(BytecodeError::UnsupportedVersion(3))
+    ));
+
+    assert!(matches!(
+        ImCompiler::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(BytecodeError::Truncated)
+    ));
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(matches!(
+        ImCompiler::from_bytes(&trailing),
+        Err(BytecodeError::TrailingBytes)
+    ));
+
+    // A well-formed file whose tape has a `return` as an operand.
+    let constants = [1.0f64.to_bits(), 2.0f64.to_bits()];
+    let tape = [
+        OpCode::Add.id(),
+        Hint::Return.cell(),
+        OpCode::Float.id(),
+        0,
+        OpCode::Float.id(),
+        1,
+    ];
+
+    let mut crafted = MAGIC.to_vec();
+    crafted.extend(VERSION.to_le_bytes());
+    crafted.extend(0u32.to_le_bytes());
+    for section in [&constants[..], &tape[..]] {
+        crafted.extend((section.len() as u32).to_le_bytes());
+        for cell in section {
+            crafted.extend(cell.to_le_bytes());
+        }
+    }
+    crafted.extend(checksum(&crafted).to_le_bytes());
+
+    assert!(matches!(
+        ImCompiler::from_bytes(&crafted),
+        Err(BytecodeError::Decode(DecodeError::InvalidHint {
+            offset: 1,
+            hint: Hint::Return
+        }))
+    ));
+    assert!(matches!(
+        ImCompiler::from_bytes(b"nope"),
+        Err(BytecodeError::BadMagic)
+    ));
+}
@@ -0,0 +1,466 @@
+//! A debugger for tape programs, enabled by the `debugger` feature.
+//!
+//! Once [attached](CallContext::attach) to a context, the debugger is
+//! consulted before and after every operation. It stops on breakpoints
+//! (offsets of operations, as shown by the [listing](Dissassembler::listing)),
+//! after stepping and when a watched global changes, handing control to a
+//! [`DebugHandler`] that inspects the program and tells it how to resume.
+//!
+//! Expressions don't carry source spans, so breakpoints can't be set on
+//! source lines yet.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Breakpoint,
+    /// A step started by a [`Command`] completed.
+    Step,
+    Watchpoint {
+        global: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Runs until the next breakpoint or watchpoint.
+    Continue,
+    /// Stops before the next operation.
+    StepInto,
+    /// Stops before the next operation that isn't nested in this one.
+    StepOver,
+    /// Stops before the next operation that isn't nested in this one's
+    /// parent.
+    StepOut,
+}
+
+pub trait DebugHandler {
+    fn stopped(&mut self, session: &mut Session<'_>) -> Command;
+}
+
+/// What the debugger does until the next stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    /// Stop before any operation at most this deep.
+    Step(usize),
+}
+
+pub struct Debugger {
+    names: Vec<String>,
+    listing: Listing,
+    breakpoints: BTreeSet<usize>,
+    /// Watched globals along with their last seen value.
+    watchpoints: Vec<(usize, Option<Value>)>,
+    /// Number of operations being executed.
+    depth: usize,
+    mode: Mode,
+    handler: Option<Box<dyn DebugHandler>>,
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("watchpoints", &self.watchpoints)
+            .field("depth", &self.depth)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debugger {
+    /// A debugger for the program compiled by `compiler`, which starts by
+    /// running until a breakpoint or watchpoint is hit.
+    pub fn new(compiler: &ImCompiler, handler: impl DebugHandler + 'static) -> Self {
+        Self {
+            names: compiler.globals.clone(),
+            listing: Dissassembler::from(compiler.clone()).listing(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            depth: 0,
+            mode: Mode::Run,
+            handler: Some(Box::new(handler)),
+        }
+    }
+
+    /// Stops before the very first operation.
+    pub fn stop_on_entry(mut self) -> Self {
+        self.mode = Mode::Step(usize::MAX);
+        self
+    }
+
+    pub fn break_at(&mut self, offset: usize) {
+        self.breakpoints.insert(offset);
+    }
+
+    pub fn clear(&mut self, offset: usize) -> bool {
+        self.breakpoints.remove(&offset)
+    }
+
+    /// Stops whenever the global called `name` changes, returns `false`
+    /// if there's no such global.
+    pub fn watch(&mut self, name: &str) -> bool {
+        let Some(idx) = self.names.iter().position(|global| global == name) else {
+            return false;
+        };
+
+        if !self.watchpoints.iter().any(|(watched, _)| *watched == idx) {
+            self.watchpoints.push((idx, None));
+        }
+
+        true
+    }
+
+    pub fn unwatch(&mut self, name: &str) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints
+            .retain(|(idx, _)| self.names.get(*idx).map(String::as_str) != Some(name));
+
+        self.watchpoints.len() != len
+    }
+
+    /// Called before the operation at `offset` runs.
+    pub fn before(&mut self, offset: usize, globals: &[Value]) {
+        for (idx, last) in &mut self.watchpoints {
+            last.get_or_insert(globals[*idx]);
+        }
+
+        let stop = match self.mode {
+            Mode::Step(depth) if self.depth <= depth => Some(Stop::Step),
+            _ if self.breakpoints.contains(&offset) => Some(Stop::Breakpoint),
+            _ => None,
+        };
+
+        if let Some(stop) = stop {
+            self.stop(offset, stop, globals);
+        }
+
+        self.depth += 1;
+    }
+
+    /// Called once the operation at `offset` returned.
+    pub fn after(&mut self, offset: usize, globals: &[Value]) {
+        self.depth -= 1;
+
+        for i in 0..self.watchpoints.len() {
+            let (idx, last) = self.watchpoints[i];
+            let new = globals[idx];
+
+            if last.is_some_and(|old| old != new) {
+                self.watchpoints[i].1 = Some(new);
+
+                let stop = Stop::Watchpoint {
+                    global: self.names[idx].clone(),
+                    old: last.unwrap(),
+                    new,
+                };
+                self.stop(offset, stop, globals);
+            }
+        }
+    }
+
+    fn stop(&mut self, offset: usize, stop: Stop, globals: &[Value]) {
+        let Some(mut handler) = self.handler.take() else {
+            return;
+        };
+
+        let mut session = Session {
+            offset,
+            stop,
+            debugger: self,
+            globals,
+        };
+        let command = handler.stopped(&mut session);
+
+        self.handler = Some(handler);
+        self.mode = match command {
+            Command::Continue => Mode::Run,
+            Command::StepInto => Mode::Step(usize::MAX),
+            Command::StepOver => Mode::Step(self.depth),
+            Command::StepOut => Mode::Step(self.depth.saturating_sub(1)),
+        };
+    }
+}
+
+/// The state of a stopped program.
+pub struct Session<'a> {
+    /// Offset of the operation about to run, or that just ran for
+    /// watchpoints.
+    pub offset: usize,
+    pub stop: Stop,
+    debugger: &'a mut Debugger,
+    globals: &'a [Value],
+}
+
+impl Session<'_> {
+    /// The listing of the current operation.
+    pub fn line(&self) -> Option<&Line> {
+        self.debugger.listing.line_at(self.offset)
+    }
+
+    pub fn listing(&self) -> &Listing {
+        &self.debugger.listing
+    }
+
+    /// Number of operations the current one is nested in.
+    pub fn depth(&self) -> usize {
+        self.debugger.depth
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let idx = self
+            .debugger
+            .names
+            .iter()
+            .position(|global| global == name)?;
+        self.globals.get(idx).copied()
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&str, Value)> {
+        self.debugger
+            .names
+            .iter()
+            .map(String::as_str)
+            .zip(self.globals.iter().copied())
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        self.debugger
+    }
+}
+
+impl CallContext {
+    /// Runs every following operation under `debugger`, the returned
+    /// handle stays usable while the program runs.
+    pub fn attach(&mut self, debugger: Debugger) -> Rc<RefCell<Debugger>> {
+        let debugger = Rc::new(RefCell::new(debugger));
+        self.debugger = Some(debugger.clone());
+
+        debugger
+    }
+}
+
+/// An interactive [`DebugHandler`] reading commands from `input`:
+///
+/// - `c` continue, `s` step into, `n` step over, `o` step out
+/// - `b <offset>` / `d <offset>` set / delete a breakpoint
+/// - `w <global>` / `u <global>` watch / unwatch a global
+/// - `p <global>` print a global, `g` print every global
+/// - `l` print the listing
+///
+/// The program continues once `input` is exhausted.
+pub struct Console<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    fn prompt(&mut self, session: &mut Session<'_>) -> std::io::Result<Command> {
+        match &session.stop {
+            Stop::Breakpoint => write!(self.output, "breakpoint at ")?,
+            Stop::Step => write!(self.output, "stepped to ")?,
+            Stop::Watchpoint { global, old, new } => {
+                write!(self.output, "{global} changed from {old:?} to {new:?} at ")?
+            }
+        }
+
+        match session.line() {
+            Some(line) => writeln!(
+                self.output,
+                "{}: {}",
+                session.offset,
+                session.listing().render(line)
+            )?,
+            None => writeln!(self.output, "{}", session.offset)?,
+        }
+
+        loop {
+            write!(self.output, "> ")?;
+            self.output.flush()?;
+
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                return Ok(Command::Continue);
+            }
+
+            let (command, arg) = match command.trim().split_once(' ') {
+                Some((command, arg)) => (command, arg.trim()),
+                None => (command.trim(), ""),
+            };
+
+            match (command, arg.parse::<usize>()) {
+                ("c", _) => return Ok(Command::Continue),
+                ("s", _) => return Ok(Command::StepInto),
+                ("n", _) => return Ok(Command::StepOver),
+                ("o", _) => return Ok(Command::StepOut),
+                ("b", Ok(offset)) => session.debugger().break_at(offset),
+                ("d", Ok(offset)) => {
+                    if !session.debugger().clear(offset) {
+                        writeln!(self.output, "no breakpoint at {offset}")?;
+                    }
+                }
+                ("w", _) | ("u", _) => {
+                    let found = if command == "w" {
+                        session.debugger().watch(arg)
+                    } else {
+                        session.debugger().unwatch(arg)
+                    };
+
+                    if !found {
+                        writeln!(self.output, "no global {arg}")?;
+                    }
+                }
+                ("p", _) => match session.global(arg) {
+                    Some(value) => writeln!(self.output, "{arg} = {value:?}")?,
+                    None => writeln!(self.output, "no global {arg}")?,
+                },
+                ("g", _) => {
+                    for (name, value) in session.globals() {
+                        writeln!(self.output, "{name} = {value:?}")?;
+                    }
+                }
+                ("l", _) => write!(self.output, "{}", session.listing())?,
+                _ => writeln!(
+                    self.output,
+                    "commands: c, s, n, o, b <offset>, d <offset>, w <global>, u <global>, p <global>, g, l"
+                )?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHandler for Console<R, W> {
+    fn stopped(&mut self, session: &mut Session<'_>) -> Command {
+        self.prompt(session).unwrap_or(Command::Continue)
+    }
+}
+
+#[test]
+pub fn debugger() {
+    use crate::expr::*;
+
+    struct Script {
+        commands: Vec<Command>,
+        stops: Rc<RefCell<Vec<(usize, Stop)>>>,
+    }
+
+    impl DebugHandler for Script {
+        fn stopped(&mut self, session: &mut Session<'_>) -> Command {
+            self.stops
+                .borrow_mut()
+                .push((session.offset, session.stop.clone()));
+
+            if self.commands.is_empty() {
+                Command::Continue
+            } else {
+                self.commands.remove(0)
+            }
+        }
+    }
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(2.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Binding::Global("y".into()).assign(Expr::Boolean(true)),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    // 0 block, 2 assign x, 4 float, 6 hint, 7 while_loop, 9 lt,
+    // 10 var, 12 float, 14 assign x, 16 add, 17 var, 19 float, 21 assign y
+    let stops = Rc::new(RefCell::new(Vec::new()));
+    let mut debugger = Debugger::new(
+        &compiler,
+        Script {
+            commands: vec![
+                Command::StepInto,
+                Command::StepOver,
+                Command::StepOver,
+                Command::Continue,
+            ],
+            stops: stops.clone(),
+        },
+    )
+    .stop_on_entry();
+    debugger.break_at(21);
+    debugger.watch("x");
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.attach(debugger);
+    context.execute();
+
+    let changed = |old: f64, new: f64| Stop::Watchpoint {
+        global: "x".into(),
+        old: Value::Float(old),
+        new: Value::Float(new),
+    };
+
+    assert_eq!(
+        *stops.borrow(),
+        vec![
+            (0, Stop::Step),
+            (2, Stop::Step),
+            (7, Stop::Step),
+            (14, changed(0.0, 1.0)),
+            (14, changed(1.0, 2.0)),
+            (21, Stop::Breakpoint),
+        ]
+    );
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let output = Output::default();
+    let console = Console {
+        input: "b 14\nc\np x\nd 14\ng\nc\n".as_bytes(),
+        output: output.clone(),
+    };
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.attach(Debugger::new(&compiler, console).stop_on_entry());
+    context.execute();
+
+    assert_eq!(
+        String::from_utf8(output.0.take()).unwrap(),
+        "stepped to 0: block -> 24 (end)
+> > breakpoint at 14: assign x
+> x = Float(0.0)
+> > x = Float(0.0)
+y = Float(0.0)
+> "
+    );
+}
@@ -0,0 +1,236 @@
+use crate::expr::{Binding, Expr};
+use crate::*;
+
+impl ImCompiler {
+    /// Reconstructs the program compiled on the tape, see [`decompile`].
+    pub fn decompile(&self) -> Result<Expr, DecodeError> {
+        decompile(&self.future_tape, &self.globals)
+    }
+}
+
+/// Reconstructs the expression compiled on `tape`, `globals` being the
+/// names of the compiler's globals.
+///
+/// Specialized operations are turned back into plain binary operations
+/// and `block_checked` into a block, so compiling the result gives back the
+/// same tape. Missing `else` and `finally` bodies are compiled as empty
+/// blocks, which decompile to `None`.
+pub fn decompile(tape: &[u64], globals: &[String]) -> Result<Expr, DecodeError> {
+    to_expr(&Node::decode(tape)?, globals)
+}
+
+fn to_expr(node: &Node, globals: &[String]) -> Result<Expr, DecodeError> {
+    let global = |index: u64| match globals.get(index as usize) {
+        Some(name) => Ok(Binding::Global(name.clone())),
+        None => Err(DecodeError::UnknownGlobal {
+            offset: node.offset + 1,
+            index,
+        }),
+    };
+    let boxed = |node: &Node| to_expr(node, globals).map(Box::new);
+    let optional = |node: &Node| match &node.kind {
+        NodeKind::Block { body, .. } if body.is_empty() => Ok(None),
+        _ => boxed(node).map(Some),
+    };
+
+    Ok(match &node.kind {
+        NodeKind::Boolean(b) => Expr::Boolean(*b),
+        NodeKind::Float(x) | NodeKind::RawFloat(x) => Expr::Float(*x),
+        NodeKind::Var(idx) | NodeKind::RawVar(idx) => Expr::Var(global(*idx)?),
+        NodeKind::Assign(idx, value) => Expr::Assign(global(*idx)?, boxed(value)?),
+        NodeKind::Block { body, .. } => Expr::Block(
+            body.iter()
+                .map(|statement| to_expr(statement, globals))
+                .collect::<Result<_, _>>()?,
+        ),
+        NodeKind::Return(value) => Expr::Return(boxed(value)?),
+        NodeKind::Yield(value) => Expr::Yield(boxed(value)?),
+        NodeKind::Coroutine(body) => Expr::Coroutine(boxed(body)?),
+        NodeKind::Resume(coroutine, value) => Expr::Resume(boxed(coroutine)?, boxed(value)?),
+        NodeKind::Done(coroutine) => Expr::Done(boxed(coroutine)?),
+        NodeKind::Throw(exception) => Expr::Throw(boxed(exception)?),
+        NodeKind::Try {
+            body,
+            binding,
+            handler,
+            finally,
+        } => Expr::Try {
+            body: boxed(body)?,
+            catch_binding: global(*binding)?,
+            handler: boxed(handler)?,
+            finally: optional(finally)?,
+        },
+        NodeKind::While(cond, body) => Expr::While(boxed(cond)?, boxed(body)?),
+        NodeKind::Conditional(branches, else_body) => {
+            let mut branches = branches
+                .iter()
+                .map(|(cond, body)| Ok((to_expr(cond, globals)?, to_expr(body, globals)?)))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter();
+
+            match branches.next() {
+                Some(first) => {
+                    Expr::Conditional(first.into(), branches.collect(), optional(else_body)?)
+                }
+                None => to_expr(else_body, globals)?,
+            }
+        }
+        NodeKind::BinaryOp(op, lhs, rhs) => Expr::BinaryOp(
+            boxed(lhs)?,
+            op.operator().expect("binary operations have an operator"),
+            boxed(rhs)?,
+        ),
+    })
+}
+
+#[test]
+pub fn decompiler() {
+    use crate::expr::Operator;
+
+    /// Deterministic xorshift, so failures are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    const OPERATORS: [Operator; 11] = [
+        Operator::Add,
+        Operator::Sub,
+        Operator::Mul,
+        Operator::Div,
+        Operator::Rem,
+        Operator::Lt,
+        Operator::Lte,
+        Operator::Gt,
+        Operator::Gte,
+        Operator::Eq,
+        Operator::Neq,
+    ];
+
+    /// Loops and returns are only generated as `statement`s, the only
+    /// place hinted instructions can be.
+    fn generate(rng: &mut Rng, depth: u32, statement: bool) -> Expr {
+        let global = |rng: &mut Rng| {
+            let name = ["x", "y", "z"][rng.below(3) as usize];
+            Binding::Global(name.into())
+        };
+
+        if depth == 0 {
+            return match rng.below(3) {
+                0 => Expr::Float(rng.below(100) as f64 / 4.0),
+                1 => Expr::Boolean(rng.below(2) == 0),
+                _ => global(rng).var(),
+            };
+        }
+
+        match rng.below(7) {
+            0 => global(rng).assign(generate(rng, depth - 1, false)),
+            1 => Expr::Block(
+                (0..rng.below(4))
+                    .map(|_| generate(rng, depth - 1, true))
+                    .collect(),
+            ),
+            2 | 3 if !statement => Expr::Block(vec![generate(rng, depth - 1, true)]),
+            2 => Expr::While(
+                generate(rng, depth - 1, false).into(),
+                generate(rng, depth - 1, true).into(),
+            ),
+            3 => Expr::Return(generate(rng, depth - 1, false).into()),
+            4 => Expr::Conditional(
+                (
+                    generate(rng, depth - 1, false),
+                    generate(rng, depth - 1, true),
+                )
+                    .into(),
+                (0..rng.below(3))
+                    .map(|_| {
+                        (
+                            generate(rng, depth - 1, false),
+                            generate(rng, depth - 1, true),
+                        )
+                    })
+                    .collect(),
+                (rng.below(2) == 0).then(|| generate(rng, depth - 1, true).into()),
+            ),
+            _ => generate(rng, depth - 1, false).op(
+                OPERATORS[rng.below(OPERATORS.len() as u64) as usize],
+                generate(rng, depth - 1, false),
+            ),
+        }
+    }
+
+    /// Blocks aren't compiled past their first return, and an empty
+    /// `else` body is the same as none.
+    fn normalize(expr: Expr) -> Expr {
+        let boxed = |expr: Box<Expr>| Box::new(normalize(*expr));
+
+        match expr {
+            Expr::Block(statements) => {
+                let end = statements
+                    .iter()
+                    .position(|statement| matches!(statement, Expr::Return(_)))
+                    .map_or(statements.len(), |idx| idx + 1);
+
+                Expr::Block(statements.into_iter().take(end).map(normalize).collect())
+            }
+            Expr::Assign(binding, value) => Expr::Assign(binding, boxed(value)),
+            Expr::Return(value) => Expr::Return(boxed(value)),
+            Expr::While(cond, body) => Expr::While(boxed(cond), boxed(body)),
+            Expr::BinaryOp(lhs, op, rhs) => Expr::BinaryOp(boxed(lhs), op, boxed(rhs)),
+            Expr::Conditional(first, elifs, else_body) => {
+                let (cond, body) = *first;
+
+                Expr::Conditional(
+                    (normalize(cond), normalize(body)).into(),
+                    elifs
+                        .into_iter()
+                        .map(|(cond, body)| (normalize(cond), normalize(body)))
+                        .collect(),
+                    else_body
+                        .map(boxed)
+                        .filter(|else_body| **else_body != Expr::Block(vec![])),
+                )
+            }
+            expr => expr,
+        }
+    }
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..500 {
+        let program = generate(&mut rng, 4, false);
+
+        let mut compiler = match passes::check_types(&program) {
+            Ok(types) => ImCompiler::with_types(types),
+            Err(_) => ImCompiler::new(),
+        };
+        compiler.compile_expr(program.clone());
+
+        let decompiled = compiler.decompile().unwrap();
+        assert_eq!(decompiled, normalize(program));
+
+        let mut recompiled = ImCompiler::new();
+        recompiled.types = compiler.types.clone();
+        recompiled.compile_expr(decompiled);
+        assert_eq!(recompiled.future_tape, compiler.future_tape);
+    }
+
+    assert_eq!(
+        decompile(
+            &ImCompiler::link(vec![], &[OpCode::Var.id(), 3])
+                .unwrap()
+                .future_tape,
+            &[]
+        ),
+        Err(DecodeError::UnknownGlobal {
+            offset: 1,
+            index: 3
+        })
+    );
+}
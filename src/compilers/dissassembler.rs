@@ -1,196 +1,513 @@
+use std::fmt::{self, Write as _};
+use std::io;
+
 pub use crate::*;
 
 pub struct Dissassembler {
-    offset: u64,
     tape: Vec<u64>,
     globals: Vec<String>,
-    level: usize,
 }
 
 impl From<ImCompiler> for Dissassembler {
     fn from(value: ImCompiler) -> Self {
-        Self {
-            offset: 0,
-            level: 0,
-            tape: value.future_tape,
-            globals: value.globals,
-        }
+        Self::new(value.future_tape, value.globals)
     }
 }
 
-macro_rules! impl_op_diss {
-    ($self:ident, $as_fn:ident, $f:path, $op:tt) => {
-        if $as_fn == $f {
-            $self.dissassemble();
-            eprint!(" {} ", stringify!($op));
-            $self.dissassemble();
-            return true;
-        }
-    };
-}
-
 impl Dissassembler {
-    fn read(&mut self) -> u64 {
-        let value = self.tape[self.offset as usize];
-        self.offset += 1;
-
-        value
+    pub fn new(tape: Vec<u64>, globals: Vec<String>) -> Self {
+        Self { tape, globals }
     }
 
-    fn peek(&mut self) -> u64 {
-        self.tape[self.offset as usize + 1]
+    /// Prints the program to stderr.
+    pub fn dissassemble_program(&self) {
+        eprintln!("{}", self.program());
     }
 
-    fn scope_in(&mut self) {
-        self.level += 4;
+    /// Renders the program as indented pseudo-source.
+    pub fn program(&self) -> String {
+        let mut out = String::new();
+        self.write_program(&mut out)
+            .expect("writing to a String can't fail");
+
+        out
     }
 
-    fn scope_out(&mut self) {
-        self.level -= 4;
+    pub fn write_program(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        match Node::decode(&self.tape) {
+            Ok(program) => self.node(out, &program, 0),
+            Err(err) => write!(out, "<invalid tape: {err}>"),
+        }
     }
 
-    pub fn dissassemble_program(&mut self) {
-        self.dissassemble();
-        eprintln!("");
+    pub fn write_program_io(&self, out: &mut impl io::Write) -> io::Result<()> {
+        out.write_all(self.program().as_bytes())
     }
 
-    pub fn dissassemble(&mut self) {
-        let element = self.read();
+    /// Lists every instruction with its offset, operation and operands.
+    ///
+    /// Unlike [`Dissassembler::program`] this doesn't need a well-formed
+    /// tape: the listing stops at the first cell that isn't understood and
+    /// the remaining cells are listed as is.
+    pub fn listing(&self) -> Listing {
+        let mut lister = Lister {
+            tape: &self.tape,
+            offset: 0,
+            nesting: 0,
+            lines: Vec::new(),
+        };
 
-        if self.handle_fn(element) {
-        } else if self.handle_literal(element) {
-        } else if self.handle_operation(element) {
+        if !self.tape.is_empty() {
+            lister.instr(0, false);
+        }
+
+        for (offset, cell) in self.tape.iter().enumerate().skip(lister.offset) {
+            lister.lines.push(Line {
+                offset,
+                depth: 0,
+                entry: Entry::Unknown(*cell),
+            });
+        }
+
+        Listing {
+            lines: lister.lines,
+            globals: self.globals.clone(),
+            len: self.tape.len(),
         }
     }
 
-    fn handle_literal(&mut self, element: u64) -> bool {
-        let as_fn: unsafe fn(&mut CallContext) -> Value = unsafe { transmute(element) };
+    pub fn write_listing(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        write!(out, "{}", self.listing())
+    }
 
-        if as_fn == literals::float {
-            let value = self.read();
-            let value: f64 = unsafe { transmute(value) };
+    pub fn write_listing_io(&self, out: &mut impl io::Write) -> io::Result<()> {
+        write!(out, "{}", self.listing())
+    }
 
-            eprint!("{}f64", value);
-        } else if as_fn == literals::tr {
-            eprint!("true");
-        } else if as_fn == literals::fl {
-            eprint!("false");
-        } else {
-            return false;
+    fn global(&self, idx: u64) -> String {
+        match self.globals.get(idx as usize) {
+            Some(name) => name.clone(),
+            None => format!("#{idx}"),
         }
-
-        true
     }
 
-    fn handle_operation(&mut self, element: u64) -> bool {
-        let as_fn: unsafe fn(&mut CallContext) -> Value = unsafe { transmute(element) };
+    fn node(&self, out: &mut impl fmt::Write, node: &Node, level: usize) -> fmt::Result {
+        match &node.kind {
+            NodeKind::Boolean(b) => write!(out, "{b}"),
+            NodeKind::Float(x) | NodeKind::RawFloat(x) => write!(out, "{x}f64"),
+            NodeKind::Var(idx) | NodeKind::RawVar(idx) => write!(out, "{}", self.global(*idx)),
+            NodeKind::Assign(idx, value) => {
+                write!(out, "global {} = ", self.global(*idx))?;
+                self.node(out, value, level)
+            }
+            NodeKind::Block { body, .. } => {
+                writeln!(out, "{{")?;
 
-        if as_fn == operations::assign {
-            eprint!("{:>ident$}global ", "", ident = self.level);
+                for statement in body {
+                    write!(out, "{:>ident$}", "", ident = level + 4)?;
+                    self.node(out, statement, level + 4)?;
+                    writeln!(out)?;
+                }
+
+                write!(out, "{:>ident$}}}", "", ident = level)
+            }
+            NodeKind::Return(value) => {
+                write!(out, "return ")?;
+                self.node(out, value, level)
+            }
+            NodeKind::Yield(value) => {
+                write!(out, "yield ")?;
+                self.node(out, value, level)
+            }
+            NodeKind::Coroutine(body) => {
+                write!(out, "coroutine ")?;
+                self.body(out, body, level)
+            }
+            NodeKind::Resume(coroutine, value) => {
+                write!(out, "resume(")?;
+                self.node(out, coroutine, level)?;
+                write!(out, ", ")?;
+                self.node(out, value, level)?;
+                write!(out, ")")
+            }
+            NodeKind::Done(coroutine) => {
+                write!(out, "done(")?;
+                self.node(out, coroutine, level)?;
+                write!(out, ")")
+            }
+            NodeKind::Throw(exception) => {
+                write!(out, "throw ")?;
+                self.node(out, exception, level)
+            }
+            NodeKind::Try {
+                body,
+                binding,
+                handler,
+                finally,
+            } => {
+                write!(out, "try ")?;
+                self.body(out, body, level)?;
+                write!(out, " catch {} ", self.global(*binding))?;
+                self.body(out, handler, level)?;
+                write!(out, " finally ")?;
+                self.body(out, finally, level)
+            }
+            NodeKind::While(cond, body) => {
+                write!(out, "while ")?;
+                self.node(out, cond, level)?;
+                write!(out, " ")?;
+                self.body(out, body, level)
+            }
+            NodeKind::Conditional(branches, else_body) => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    write!(out, "{}", if i == 0 { "if " } else { " elif " })?;
+                    self.node(out, cond, level)?;
+                    write!(out, " ")?;
+                    self.body(out, body, level)?;
+                }
 
-            let idx = self.read() as usize;
-            eprint!("{}", self.globals[idx]);
+                write!(out, " else ")?;
+                self.body(out, else_body, level)
+            }
+            NodeKind::BinaryOp(op, lhs, rhs) => {
+                let operator = op.operator().expect("binary operations have an operator");
 
-            eprint!(" = ");
-            self.dissassemble();
-        } else if as_fn == operations::var {
-            let idx = self.read() as usize;
-            eprint!("{}", self.globals[idx]);
-            return true;
+                self.operand(out, lhs, level)?;
+                write!(out, " {} ", operator.symbol())?;
+                self.operand(out, rhs, level)
+            }
         }
+    }
 
-        impl_op_diss!(self, as_fn, operations::native_op_add, +);
-        impl_op_diss!(self, as_fn, operations::native_op_sub, -);
-        impl_op_diss!(self, as_fn, operations::native_op_mul, *);
-        impl_op_diss!(self, as_fn, operations::native_op_div, /);
-        impl_op_diss!(self, as_fn, operations::native_op_rem, %);
-        impl_op_diss!(self, as_fn, operations::native_op_eq, ==);
-        impl_op_diss!(self, as_fn, operations::native_op_eq, !=);
-        impl_op_diss!(self, as_fn, operations::native_op_gt, >);
-        impl_op_diss!(self, as_fn, operations::native_op_gte, >=);
-        impl_op_diss!(self, as_fn, operations::native_op_lt, <);
-        impl_op_diss!(self, as_fn, operations::native_op_lte, <=);
+    /// Writes the body of a `while`, a conditional branch, a coroutine or a
+    /// `try`, in braces.
+    fn body(&self, out: &mut impl fmt::Write, body: &Node, level: usize) -> fmt::Result {
+        if let NodeKind::Block { .. } = body.kind {
+            return self.node(out, body, level);
+        }
 
-        true
+        write!(out, "{{\n{:>ident$}", "", ident = level + 4)?;
+        self.node(out, body, level + 4)?;
+        write!(out, "\n{:>ident$}}}", "", ident = level)
     }
 
-    fn handle_fn(&mut self, element: u64) -> bool {
-        let as_fn: unsafe fn(&mut CallContext) -> Value = unsafe { transmute(element) };
-        let as_opt_fn: unsafe fn(&mut CallContext) -> Option<Value> = unsafe { transmute(element) };
-
-        if as_fn == flow::block {
-            eprint!("\n{:>ident$}{{\n", "", ident = self.level);
-            self.scope_in();
+    fn operand(&self, out: &mut impl fmt::Write, operand: &Node, level: usize) -> fmt::Result {
+        if let NodeKind::BinaryOp(..) = operand.kind {
+            write!(out, "(")?;
+            self.node(out, operand, level)?;
+            write!(out, ")")
+        } else {
+            self.node(out, operand, level)
+        }
+    }
+}
 
-            let next_instr_idx = self.read();
+/// A raw listing of a tape, see [`Dissassembler::listing`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub globals: Vec<String>,
+    /// Length of the listed tape.
+    pub len: usize,
+}
 
-            while self.offset != next_instr_idx {
-                self.dissassemble();
-                eprint!("\n");
-            }
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub offset: usize,
+    /// Nesting level of the instruction.
+    pub depth: usize,
+    pub entry: Entry,
+}
 
-            self.scope_out();
-            eprint!("{:>ident$}}}", "", ident = self.level);
-        } else if as_fn == flow::block_checked {
-            eprint!("\n{:>ident$}{{\n", "", ident = self.level);
-            self.scope_in();
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Hint(Hint),
+    Op {
+        op: OpCode,
+        arguments: Vec<Argument>,
+    },
+    /// The false jump starting a branch of a `conditional`.
+    Branch {
+        target: u64,
+    },
+    Unknown(u64),
+}
 
-            let next_instr_idx = self.read();
+/// An operand stored in the cells of an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Argument {
+    Float(f64),
+    Global(u64),
+    Jump(u64),
+    Count(u64),
+}
 
-            while self.offset != next_instr_idx {
-                let pk = self.read();
+impl Listing {
+    /// The line listing the cell at `offset`.
+    pub fn line_at(&self, offset: usize) -> Option<&Line> {
+        self.lines.iter().find(|line| line.offset == offset)
+    }
 
-                match pk {
-                    1001 => {
-                        eprint!("{:>ident$}return ", "", ident = self.level);
-                        self.dissassemble();
-                    }
+    /// Renders `line` without its offset and indentation.
+    pub fn render(&self, line: &Line) -> String {
+        let mut out = String::new();
+        self.write_entry(&mut out, &line.entry)
+            .expect("writing to a String can't fail");
 
-                    1003 => {
-                        self.dissassemble();
-                    }
+        out
+    }
 
-                    _ => {
-                        self.dissassemble();
+    fn write_entry(&self, out: &mut impl fmt::Write, entry: &Entry) -> fmt::Result {
+        match entry {
+            Entry::Hint(hint) => write!(out, "hint {hint:?}"),
+            Entry::Op { op, arguments } => {
+                write!(out, "{}", op.name())?;
+
+                for (i, argument) in arguments.iter().enumerate() {
+                    out.write_str(if i == 0 { " " } else { ", " })?;
+
+                    match *argument {
+                        Argument::Float(x) => write!(out, "{x:?}")?,
+                        Argument::Global(idx) => match self.globals.get(idx as usize) {
+                            Some(name) => write!(out, "{name}")?,
+                            None => write!(out, "#{idx}")?,
+                        },
+                        Argument::Jump(target) => self.target(out, target)?,
+                        Argument::Count(count) => write!(out, "{count}")?,
                     }
                 }
 
-                eprint!("\n");
+                Ok(())
+            }
+            Entry::Branch { target } => {
+                write!(out, "branch ")?;
+                self.target(out, *target)
             }
+            Entry::Unknown(cell) => write!(out, "{cell:#x}"),
+        }
+    }
 
-            self.scope_out();
-            eprint!("{:>ident$}}}", "", ident = self.level);
-        } else if as_opt_fn == flow::while_loop {
-            eprint!("{:>ident$}while ", "", ident = self.level);
-            let _ = self.read();
-            self.dissassemble();
-            eprint!(" {{\n");
+    fn target(&self, out: &mut impl fmt::Write, target: u64) -> fmt::Result {
+        write!(out, "-> {target} ")?;
 
-            self.scope_in();
+        if target as usize == self.len {
+            return write!(out, "(end)");
+        }
 
-            let pk = self.read();
-            match pk {
-                1001 => {
-                    eprint!("{:>ident$}return ", "", ident = self.level);
-                    self.dissassemble();
-                }
+        match self.line_at(target as usize).map(|line| &line.entry) {
+            Some(Entry::Hint(_)) => write!(out, "(hint)"),
+            Some(Entry::Op { op, .. }) => write!(out, "({})", op.name()),
+            Some(Entry::Branch { .. }) => write!(out, "(branch)"),
+            Some(Entry::Unknown(_)) | None => write!(out, "(?)"),
+        }
+    }
+}
 
-                1003 => {
-                    self.dissassemble();
-                }
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            write!(
+                f,
+                "{:>5}  {:ident$}",
+                line.offset,
+                "",
+                ident = line.depth * 2
+            )?;
+            self.write_entry(f, &line.entry)?;
+            f.write_char('\n')?;
+        }
 
-                _ => {
-                    self.dissassemble();
-                }
-            }
+        Ok(())
+    }
+}
 
-            self.scope_out();
+struct Lister<'a> {
+    tape: &'a [u64],
+    offset: usize,
+    nesting: usize,
+    lines: Vec<Line>,
+}
 
-            eprint!("\n{:>ident$}}}", "", ident = self.level);
-        } else {
-            return false;
+impl Lister<'_> {
+    fn read(&mut self) -> Option<u64> {
+        let cell = *self.tape.get(self.offset)?;
+        self.offset += 1;
+
+        Some(cell)
+    }
+
+    /// Lists the instruction at the current offset, hinted only if it's a
+    /// `statement`. Returns `None` when the tape can't be followed any
+    /// further.
+    fn instr(&mut self, depth: usize, statement: bool) -> Option<()> {
+        if self.nesting == Node::MAX_DEPTH {
+            return None;
         }
 
-        true
+        self.nesting += 1;
+        self.instr_at(depth, statement)?;
+        self.nesting -= 1;
+
+        Some(())
+    }
+
+    fn instr_at(&mut self, depth: usize, statement: bool) -> Option<()> {
+        let offset = self.offset;
+        let cell = self.read()?;
+
+        let op = match Instr::decode(cell, OpCode::from_address, statement) {
+            Ok(Instr::Op(op)) => op,
+            Ok(Instr::Hint(hint)) => {
+                self.lines.push(Line {
+                    offset,
+                    depth,
+                    entry: Entry::Hint(hint),
+                });
+
+                return self.instr(depth, false);
+            }
+            Err(_) => {
+                self.offset = offset;
+                return None;
+            }
+        };
+
+        let line = self.lines.len();
+        self.lines.push(Line {
+            offset,
+            depth,
+            entry: Entry::Op {
+                op,
+                arguments: Vec::new(),
+            },
+        });
+
+        let mut count = 0;
+        let mut target = 0;
+
+        for operand in op.operands() {
+            let argument = match operand {
+                Operand::Float => Argument::Float(f64::from_bits(self.read()?)),
+                Operand::Global => Argument::Global(self.read()?),
+                Operand::Jump => {
+                    target = self.read()?;
+                    Argument::Jump(target)
+                }
+                Operand::Count => {
+                    count = self.read()?;
+                    Argument::Count(count)
+                }
+                Operand::Value | Operand::Raw | Operand::Statement => {
+                    self.instr(depth + 1, *operand == Operand::Statement)?;
+                    continue;
+                }
+                Operand::Body => {
+                    while (self.offset as u64) < target {
+                        self.instr(depth + 1, true)?;
+                    }
+                    continue;
+                }
+                Operand::Branch => {
+                    for _ in 0..count {
+                        let offset = self.offset;
+                        let target = self.read()?;
+
+                        self.lines.push(Line {
+                            offset,
+                            depth: depth + 1,
+                            entry: Entry::Branch { target },
+                        });
+                        self.instr(depth + 2, false)?;
+                        self.instr(depth + 2, true)?;
+                    }
+                    continue;
+                }
+            };
+
+            if let Entry::Op { arguments, .. } = &mut self.lines[line].entry {
+                arguments.push(argument);
+            }
+        }
+
+        Some(())
+    }
+}
+
+#[test]
+pub fn dissassembler() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(3.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Expr::Conditional(
+            (
+                x().var().op(Operator::Eq, Expr::Float(3.0)),
+                Expr::Return(Expr::Boolean(true).into()),
+            )
+                .into(),
+            vec![],
+            None,
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let dissassembler = Dissassembler::from(compiler);
+
+    assert_eq!(
+        dissassembler.program(),
+        "{
+    global x = 0f64
+    while x < 3f64 {
+        global x = x + 1f64
+    }
+    if x == 3f64 {
+        return true
+    } else {
     }
+}"
+    );
+
+    let mut listing = Vec::new();
+    dissassembler.write_listing_io(&mut listing).unwrap();
+
+    assert_eq!(
+        String::from_utf8(listing).unwrap(),
+        "    0  block_checked -> 34 (end)
+    2    assign x
+    4      float 0.0
+    6    hint While
+    7    while_loop -> 21 (conditional)
+    9      lt
+   10        var x
+   12        float 3.0
+   14      assign x
+   16        add
+   17          var x
+   19          float 1.0
+   21    conditional 1, -> 34 (end)
+   24      branch -> 32 (block)
+   25        eq
+   26          var x
+   28          float 3.0
+   30        hint Return
+   31        true
+   32      block -> 34 (end)
+"
+    );
+
+    let mut corrupted = dissassembler.tape.clone();
+    corrupted[9] = 7;
+
+    let listing = Dissassembler::new(corrupted, vec![]).listing();
+    assert_eq!(listing.lines[5].entry, Entry::Unknown(7));
+    assert_eq!(listing.lines.len(), 5 + 34 - 9);
 }
@@ -1,3 +1,25 @@
+/// Evaluates the operation at the current offset as an operand of the
+/// operation at `$offset`. If the program halts meanwhile, the caller saves
+/// `$resume` (the operand's offset by default) and `$value` to carry on
+/// from, and returns.
+macro_rules! eval {
+    ($ctx:ident, $t:ty, $offset:expr) => {{
+        let resume = $ctx.tape.offset;
+        eval!($ctx, $t, $offset, resume, None)
+    }};
+    ($ctx:ident, $t:ty, $offset:expr, $resume:expr, $value:expr) => {{
+        let resume = $resume;
+        let result = $ctx.tape.get_next_func::<$t>().call($ctx);
+
+        if $ctx.halted() {
+            $ctx.suspend($offset, resume, $value);
+            return OpResult::unwound();
+        }
+
+        result
+    }};
+}
+
 pub mod literals {
     use crate::*;
 
@@ -16,90 +38,400 @@ pub mod literals {
 }
 
 pub mod flow {
+    use std::ops::ControlFlow;
+
     use crate::*;
 
-    // TODO: Handle conditionals
-    macro_rules! loop_body {
-        ($ctx:ident, $next_instr:ident) => {
-            if 1001 <= $ctx.tape.read() && $ctx.tape.read() <= 2000 as u64 {
-                let v = $ctx.tape.read();
-                $ctx.tape.skip(1);
-
-                match v {
-                    1001 => {
-                        let value = $ctx.tape.get_next_func::<Value>().call($ctx);
-                        $ctx.tape.move_to($next_instr as usize);
-                        return value.into();
-                    }
-                    1003 => {
-                        let value = $ctx.tape.get_next_func::<Option<Value>>().call($ctx);
-                        if let Some(x) = value {
-                            $ctx.tape.move_to($next_instr as usize);
-                            return x.into();
-                        }
-                        continue;
-                    }
-                    x => panic!("Invalid block hint: {x}"),
+    /// Runs the statement at the current offset of the block, loop or
+    /// conditional at `offset`, breaking with the value of the `return` it
+    /// reached.
+    #[inline(always)]
+    unsafe fn statement(ctx: &mut CallContext, offset: usize) -> ControlFlow<Value, Value> {
+        let start = ctx.tape.offset;
+
+        let cell = ctx.tape.read();
+
+        let flow = match Hint::from_cell(cell) {
+            Some(Hint::Return) => {
+                ctx.tape.skip(1);
+                ControlFlow::Break(ctx.tape.get_next_func::<Value>().call(ctx))
+            }
+            Some(Hint::While) => {
+                ctx.tape.skip(1);
+
+                match ctx.tape.get_next_func::<Option<Value>>().call(ctx) {
+                    Some(value) => ControlFlow::Break(value),
+                    None => ControlFlow::Continue(Value::Nil),
+                }
+            }
+            _ if Hint::is_tagged(cell) => panic!("Invalid block hint: {cell:#x}"),
+            _ => {
+                let value = ctx.tape.get_next_func::<Value>().call(ctx);
+
+                // Blocks and conditionals leave the `return` they reached to
+                // the statement they're in.
+                match ctx.take_return() {
+                    Some(value) => ControlFlow::Break(value),
+                    None => ControlFlow::Continue(value),
                 }
             }
         };
+
+        // Statements are resumed from their hint.
+        if ctx.halted() {
+            ctx.suspend(offset, start, None);
+            return ControlFlow::Continue(Value::Nil);
+        }
+
+        flow
     }
-    pub unsafe fn block_checked(ctx: &mut CallContext) -> Value {
-        let next_instr = ctx.tape.get_next() as usize;
+
+    /// Runs the statements of the block at `offset` up to `next_instr`,
+    /// returning the value of the `return` they reached.
+    #[inline(always)]
+    unsafe fn statements(ctx: &mut CallContext, offset: usize, next_instr: usize) -> Option<Value> {
+        if let Some(frame) = ctx.resumed(offset) {
+            ctx.tape.move_to(frame.resume);
+        }
 
         while ctx.tape.offset < next_instr {
-            loop_body!(ctx, next_instr);
+            if ctx.interrupted() {
+                ctx.suspend(offset, ctx.tape.offset, None);
+                return None;
+            }
+
+            if let ControlFlow::Break(value) = statement(ctx, offset) {
+                ctx.tape.move_to(next_instr);
+                return Some(value);
+            }
 
-            let func = ctx.tape.get_next_func::<Value>();
-            func.call(ctx);
+            if ctx.halted() {
+                return None;
+            }
         }
 
-        Value::Nil
+        None
     }
 
+    /// A block whose value is used, it evaluates to the value of the
+    /// `return` its statements reached.
+    ///
+    /// # Safety
+    ///
+    /// The tape must be positioned at the jump past the block.
+    pub unsafe fn block_checked(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+        let next_instr = ctx.tape.get_next() as usize;
+
+        statements(ctx, offset, next_instr).unwrap_or(Value::Nil)
+    }
+
+    /// A block used as a statement, the `return` its statements reached
+    /// returns from the enclosing block.
+    ///
+    /// # Safety
+    ///
+    /// The tape must be positioned at the jump past the block.
     pub unsafe fn block(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
         let next_instr = ctx.tape.get_next() as usize;
 
-        while ctx.tape.offset < next_instr {
-            let func = ctx.tape.get_next_func::<Value>();
-            func.call(ctx);
+        if let Some(value) = statements(ctx, offset, next_instr) {
+            ctx.return_with(value);
         }
+
         Value::Nil
     }
 
     pub unsafe fn while_loop(ctx: &mut CallContext) -> Option<Value> {
+        let offset = ctx.tape.offset - 1;
         let next_idx: u64 = ctx.tape.get_next();
         let tape_ptr = ctx.tape.save();
 
-        while ctx.tape.get_next_func::<Value>().call(ctx).truthy() {
-            loop_body!(ctx, next_idx);
+        // Whether to carry on from the body rather than the condition, and
+        // whether the iteration was already paid for and checked for interrupts.
+        let (mut in_body, mut paid) = (false, false);
+
+        if let Some(frame) = ctx.resumed(offset) {
+            if frame.resume != tape_ptr.0 {
+                ctx.tape.move_to(frame.resume);
+                in_body = true;
+            }
+            paid = true;
+        }
+
+        loop {
+            if !in_body {
+                if !paid && (ctx.interrupted() || !ctx.spend()) {
+                    ctx.suspend(offset, tape_ptr.0, None);
+                    return None;
+                }
+                paid = false;
+
+                if !eval!(ctx, Value, offset).truthy() {
+                    break;
+                }
+            }
+            in_body = false;
+
+            if let ControlFlow::Break(value) = statement(ctx, offset) {
+                ctx.tape.move_to(next_idx as usize);
+                return Some(value);
+            }
+
+            if ctx.halted() {
+                return None;
+            }
 
-            ctx.tape.get_next_func::<Value>().call(ctx);
             ctx.tape.restore(tape_ptr);
         }
 
-        ctx.tape.skip(next_idx as usize);
+        ctx.tape.move_to(next_idx as usize);
 
         None
     }
 
-    pub unsafe fn conditional(ctx: &mut CallContext) -> (bool, Value) {
+    /// Halts the program with the value of its operand, evaluating to the
+    /// value it's [resumed](CallContext::resume) with.
+    ///
+    /// # Safety
+    ///
+    /// The tape must be positioned at the operand.
+    pub unsafe fn yield_value(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+
+        // Frames saved while evaluating the operand resume from the operand.
+        if let Some(frame) = ctx.resumed(offset) {
+            if frame.resume > offset + 1 {
+                ctx.tape.move_to(frame.resume);
+                return frame.value.unwrap_or(Value::Nil);
+            }
+        }
+
+        let value = eval!(ctx, Value, offset);
+
+        ctx.halt(Outcome::Yielded(value));
+        ctx.suspend(offset, ctx.tape.offset, None);
+
+        Value::Nil
+    }
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the exception operand.
+    pub unsafe fn throw(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+        ctx.resumed(offset);
+
+        let exception = eval!(ctx, Value, offset);
+        ctx.throw(exception);
+
+        Value::Nil
+    }
+
+    /// Runs the body, then the handler if the body threw, then the
+    /// `finally` body. The `finally` body resumes from the `try` itself
+    /// when it runs with an exception to rethrow afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The tape must be positioned at the handler and `finally` jumps.
+    pub unsafe fn try_catch(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+        let handler_jmp = ctx.tape.get_next() as usize;
+        let finally_jmp = ctx.tape.get_next() as usize;
+        let idx = ctx.tape.get_next() as usize;
+        let body = ctx.tape.offset;
+
+        // Value of the body or handler, and the exception the handler threw.
+        let (mut resume, mut value, mut thrown) = match ctx.resumed(offset) {
+            Some(frame) if frame.resume == offset => (finally_jmp, Value::Nil, frame.value),
+            Some(frame) => (frame.resume, frame.value.unwrap_or(Value::Nil), None),
+            None => (body, Value::Nil, None),
+        };
+
+        if resume < handler_jmp {
+            value = ctx.tape.get_next_func::<Value>().call(ctx);
+
+            match ctx.catch() {
+                Some(exception) => {
+                    match ctx.globals.get_mut(idx) {
+                        Some(global) => *global = exception,
+                        None => ctx.throw(Value::Error(RuntimeError::UnknownGlobal)),
+                    }
+
+                    if ctx.halted() {
+                        return Value::Nil;
+                    }
+
+                    resume = handler_jmp;
+                }
+                None if ctx.halted() => {
+                    ctx.suspend(offset, body, None);
+                    return Value::Nil;
+                }
+                None => resume = finally_jmp,
+            }
+        }
+
+        if resume < finally_jmp {
+            ctx.tape.move_to(handler_jmp);
+            value = ctx.tape.get_next_func::<Value>().call(ctx);
+
+            match ctx.catch() {
+                Some(exception) => thrown = Some(exception),
+                None if ctx.halted() => {
+                    ctx.suspend(offset, handler_jmp, None);
+                    return Value::Nil;
+                }
+                None => {}
+            }
+        }
+
+        ctx.tape.move_to(finally_jmp);
+        ctx.tape.get_next_func::<Value>().call(ctx);
+
+        // An exception thrown by the `finally` body replaces the pending one.
+        if ctx.halted() {
+            if !ctx.is_throwing() {
+                match thrown {
+                    Some(exception) => ctx.suspend(offset, offset, Some(exception)),
+                    None => ctx.suspend(offset, finally_jmp, Some(value)),
+                }
+            }
+
+            return Value::Nil;
+        }
+
+        if let Some(exception) = thrown {
+            ctx.throw(exception);
+            return Value::Nil;
+        }
+
+        value
+    }
+
+    /// Evaluates the body at the current offset of the conditional at
+    /// `offset`, the `return` it reached returns from the enclosing block.
+    #[inline(always)]
+    unsafe fn branch(ctx: &mut CallContext, offset: usize) -> Value {
+        match statement(ctx, offset) {
+            ControlFlow::Break(value) => {
+                ctx.return_with(value);
+                value
+            }
+            ControlFlow::Continue(value) => value,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the branch count.
+    pub unsafe fn conditional(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
         let branch_amount = ctx.tape.get_next();
         let end_jmp = ctx.tape.get_next();
 
+        let resume = ctx.resumed(offset).map(|frame| frame.resume);
+
         for _ in 0..branch_amount {
             let if_false_jmp = ctx.tape.get_next();
-            let res = ctx.tape.get_next_func::<Value>().call(ctx).truthy();
+
+            // When resuming, skip to the branch being run, then to its body
+            // if its condition held.
+            match resume {
+                Some(resume) if resume >= if_false_jmp as usize => {
+                    ctx.tape.move_to(if_false_jmp as usize);
+                    continue;
+                }
+                Some(resume) if resume > ctx.tape.offset => {
+                    ctx.tape.move_to(resume);
+                    let value = branch(ctx, offset);
+                    ctx.tape.move_to(end_jmp as usize);
+                    return value;
+                }
+                _ => {}
+            }
+
+            let res = eval!(ctx, Value, offset).truthy();
 
             if res {
-                let value = ctx.tape.get_next_func::<Value>().call(ctx);
+                let value = branch(ctx, offset);
                 ctx.tape.move_to(end_jmp as usize);
-                return (false, value);
+                return value;
             }
             ctx.tape.move_to(if_false_jmp as usize);
         }
 
-        return (false, ctx.tape.get_next_func::<Value>().call(ctx));
+        branch(ctx, offset)
+    }
+}
+
+pub mod coroutines {
+    use crate::*;
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the jump past the coroutine's body.
+    pub unsafe fn create(ctx: &mut CallContext) -> Value {
+        let next_instr = ctx.tape.get_next() as usize;
+        let body = ctx.tape.offset;
+
+        ctx.tape.move_to(next_instr);
+        ctx.spawn(body)
+    }
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the handle and sent value operands.
+    pub unsafe fn resume(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+
+        let (handle, sent) = match ctx.resumed(offset) {
+            // The program halted in the coroutine's body.
+            Some(Frame {
+                resume,
+                value: Some(handle),
+                ..
+            }) if resume == offset => (handle, None),
+            Some(Frame {
+                resume,
+                value: Some(handle),
+                ..
+            }) => {
+                ctx.tape.move_to(resume);
+                (
+                    handle,
+                    Some(eval!(ctx, Value, offset, resume, Some(handle))),
+                )
+            }
+            _ => {
+                let handle = eval!(ctx, Value, offset);
+                let sent = eval!(ctx, Value, offset, ctx.tape.offset, Some(handle));
+
+                (handle, Some(sent))
+            }
+        };
+
+        ctx.run_coroutine(offset, handle, sent)
+    }
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the handle operand.
+    pub unsafe fn done(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
+        ctx.resumed(offset);
+
+        let handle = eval!(ctx, Value, offset);
+
+        match ctx.coroutine(handle) {
+            Some(coroutine) => Value::Boolean(coroutine.status == CoroutineStatus::Done),
+            None => {
+                ctx.throw(Value::Error(RuntimeError::NotACoroutine));
+                Value::Nil
+            }
+        }
     }
 }
 
@@ -108,14 +440,27 @@ pub mod operations {
 
     pub unsafe fn var(ctx: &mut CallContext) -> Value {
         let idx = ctx.tape.get_next();
-        return ctx.globals[idx as usize].clone();
+
+        match ctx.globals.get(idx as usize) {
+            Some(value) => *value,
+            None => {
+                ctx.throw(Value::Error(RuntimeError::UnknownGlobal));
+                Value::Nil
+            }
+        }
     }
 
     pub unsafe fn assign(ctx: &mut CallContext) -> Value {
+        let offset = ctx.tape.offset - 1;
         let idx = ctx.tape.get_next();
-        let value = ctx.tape.get_next_func::<Value>();
+        ctx.resumed(offset);
 
-        ctx.globals[idx as usize] = transmute(value.call(ctx));
+        let value = eval!(ctx, Value, offset);
+
+        match ctx.globals.get_mut(idx as usize) {
+            Some(global) => *global = value,
+            None => ctx.throw(Value::Error(RuntimeError::UnknownGlobal)),
+        }
 
         Value::Nil
     }
@@ -123,45 +468,58 @@ pub mod operations {
     macro_rules! impl_op {
         ($name:ident, $op:tt) => {
             pub unsafe fn $name(ctx: &mut CallContext) -> Value {
-                let lhs = ctx.tape.get_next_func::<Value>().call(ctx);
-                let rhs = ctx.tape.get_next_func::<Value>().call(ctx);
+                let offset = ctx.tape.offset - 1;
+
+                let lhs = match ctx.resumed(offset) {
+                    Some(Frame {
+                        resume,
+                        value: Some(lhs),
+                        ..
+                    }) => {
+                        ctx.tape.move_to(resume);
+                        lhs
+                    }
+                    _ => eval!(ctx, Value, offset),
+                };
+                let rhs = eval!(ctx, Value, offset, ctx.tape.offset, Some(lhs));
 
-                impl_apply_op!(lhs, $op, rhs)
+                impl_apply_op!(ctx, lhs, $op, rhs)
             }
         };
     }
 
     macro_rules! impl_apply_arithmetic {
-        ($lhs:ident, $op:tt, $rhs:ident) => {{
-            if let Value::Float(f_1) = $lhs {
-                if let Value::Float(f_2) = $rhs {
+        ($ctx:ident, $lhs:ident, $op:tt, $rhs:ident) => {{
+            if let Some(f_1) = $lhs.as_float() {
+                if let Some(f_2) = $rhs.as_float() {
                     //println!("Test: {f_1} - {f_2}");
                     return Value::Float(f_1 $op f_2);
                 }
             }
 
-            panic!("Invalid arguments!");
+            $ctx.throw(Value::Error(RuntimeError::InvalidOperands));
+            Value::Nil
         }};
     }
 
     macro_rules! impl_apply_cmp {
-        ($lhs:ident, $op:tt, $rhs:ident) => {
+        ($ctx:ident, $lhs:ident, $op:tt, $rhs:ident) => {
             Value::Boolean($lhs $op $rhs)
         };
     }
 
     macro_rules! impl_apply_op {
-        ($lhs:ident, +, $rhs:ident) => {impl_apply_arithmetic!($lhs, +, $rhs)};
-        ($lhs:ident, -, $rhs:ident) => {impl_apply_arithmetic!($lhs, -, $rhs)};
-        ($lhs:ident, *, $rhs:ident) => {impl_apply_arithmetic!($lhs, *, $rhs)};
-        ($lhs:ident, /, $rhs:ident) => {impl_apply_arithmetic!($lhs, /, $rhs)};
-        ($lhs:ident, %, $rhs:ident) => {impl_apply_arithmetic!($lhs, +, $rhs)};
-        ($lhs:ident, ==, $rhs:ident) => {impl_apply_cmp!($lhs, ==, $rhs)};
-        ($lhs:ident, !=, $rhs:ident) => {impl_apply_cmp!($lhs, !=, $rhs)};
-        ($lhs:ident, >, $rhs:ident) => {impl_apply_cmp!($lhs, >, $rhs)};
-        ($lhs:ident, >=, $rhs:ident) => {impl_apply_cmp!($lhs, >=, $rhs)};
-        ($lhs:ident, <, $rhs:ident) => {impl_apply_cmp!($lhs, <, $rhs)};
-        ($lhs:ident, <=, $rhs:ident) => {impl_apply_cmp!($lhs, <=, $rhs)};
+        ($ctx:ident, $lhs:ident, +, $rhs:ident) => {impl_apply_arithmetic!($ctx, $lhs, +, $rhs)};
+        ($ctx:ident, $lhs:ident, -, $rhs:ident) => {impl_apply_arithmetic!($ctx, $lhs, -, $rhs)};
+        ($ctx:ident, $lhs:ident, *, $rhs:ident) => {impl_apply_arithmetic!($ctx, $lhs, *, $rhs)};
+        ($ctx:ident, $lhs:ident, /, $rhs:ident) => {impl_apply_arithmetic!($ctx, $lhs, /, $rhs)};
+        ($ctx:ident, $lhs:ident, %, $rhs:ident) => {impl_apply_arithmetic!($ctx, $lhs, %, $rhs)};
+        ($ctx:ident, $lhs:ident, ==, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, ==, $rhs)};
+        ($ctx:ident, $lhs:ident, !=, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, !=, $rhs)};
+        ($ctx:ident, $lhs:ident, >, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, >, $rhs)};
+        ($ctx:ident, $lhs:ident, >=, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, >=, $rhs)};
+        ($ctx:ident, $lhs:ident, <, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, <, $rhs)};
+        ($ctx:ident, $lhs:ident, <=, $rhs:ident) => {impl_apply_cmp!($ctx, $lhs, <=, $rhs)};
     }
 
     impl_op!(native_op_add, +);
@@ -176,3 +534,108 @@ pub mod operations {
     impl_op!(native_op_lt, <);
     impl_op!(native_op_lte, <=);
 }
+
+/// Operations emitted when both operands are known to be floats. Their
+/// operands are `raw_*` operations returning plain `f64`s, so fused
+/// arithmetic never goes through `Value`.
+pub mod specialized {
+    use crate::*;
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the float operand.
+    pub unsafe fn raw_float(ctx: &mut CallContext) -> f64 {
+        ctx.tape.get_next_float()
+    }
+
+    /// # Safety
+    ///
+    /// The tape must be positioned at the global's index.
+    pub unsafe fn raw_var(ctx: &mut CallContext) -> f64 {
+        let idx = ctx.tape.get_next();
+
+        match ctx.globals.get(idx as usize).map(|value| value.as_float()) {
+            Some(Some(f)) => f,
+            Some(None) => {
+                ctx.throw(Value::Error(RuntimeError::InvalidOperands));
+                0.0
+            }
+            None => {
+                ctx.throw(Value::Error(RuntimeError::UnknownGlobal));
+                0.0
+            }
+        }
+    }
+
+    /// Evaluates both `f64` operands of the operation at `offset`.
+    macro_rules! operands {
+        ($ctx:ident, $offset:ident) => {{
+            let lhs = match $ctx.resumed($offset) {
+                Some(Frame {
+                    resume,
+                    value: Some(lhs),
+                    ..
+                }) => {
+                    $ctx.tape.move_to(resume);
+                    lhs.as_float().unwrap()
+                }
+                _ => eval!($ctx, f64, $offset),
+            };
+            let rhs = eval!(
+                $ctx,
+                f64,
+                $offset,
+                $ctx.tape.offset,
+                Some(Value::Float(lhs))
+            );
+
+            (lhs, rhs)
+        }};
+    }
+
+    macro_rules! impl_raw_op {
+        ($name:ident, $op:tt) => {
+            /// # Safety
+            ///
+            /// The tape must be positioned at two `raw_*` operands.
+            pub unsafe fn $name(ctx: &mut CallContext) -> f64 {
+                let offset = ctx.tape.offset - 1;
+                let (lhs, rhs) = operands!(ctx, offset);
+
+                lhs $op rhs
+            }
+        };
+    }
+
+    macro_rules! impl_f64_op {
+        ($name:ident, $variant:ident, $op:tt) => {
+            /// # Safety
+            ///
+            /// The tape must be positioned at two `raw_*` operands.
+            pub unsafe fn $name(ctx: &mut CallContext) -> Value {
+                let offset = ctx.tape.offset - 1;
+                let (lhs, rhs) = operands!(ctx, offset);
+
+                Value::$variant(lhs $op rhs)
+            }
+        };
+    }
+
+    impl_raw_op!(raw_add, +);
+    impl_raw_op!(raw_sub, -);
+    impl_raw_op!(raw_mul, *);
+    impl_raw_op!(raw_div, /);
+    impl_raw_op!(raw_rem, %);
+
+    impl_f64_op!(f64_add, Float, +);
+    impl_f64_op!(f64_sub, Float, -);
+    impl_f64_op!(f64_mul, Float, *);
+    impl_f64_op!(f64_div, Float, /);
+    impl_f64_op!(f64_rem, Float, %);
+    impl_f64_op!(f64_eq, Boolean, ==);
+    impl_f64_op!(f64_neq, Boolean, !=);
+    impl_f64_op!(f64_gt, Boolean, >);
+    impl_f64_op!(f64_gte, Boolean, >=);
+    impl_f64_op!(f64_lt, Boolean, <);
+    impl_f64_op!(f64_lte, Boolean, <=);
+}
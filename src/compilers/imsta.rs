@@ -1,32 +1,90 @@
 use std::mem::transmute;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use crate::expr::{Binding, Expr, Operator};
 use crate::*;
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-pub enum Value {
-    Nil,
-    Boolean(bool),
-    Float(f64),
+/// Annotates the instruction following it on the tape, only
+/// [statements](Operand::Statement) are annotated.
+///
+/// Hint cells carry a tag in their top 16 bits that operation addresses
+/// (user space pointers) and [ids](OpCode::id) never have, so the cell an
+/// instruction starts with is unambiguously one or the other. Every other
+/// cell is an operand, whose meaning is given by [`OpCode::operands`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hint {
+    Return = 1,
+    Break = 2,
+    While = 3,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Hint {
-    Return = 1001,
-    Break = 1002,
-    While = 1003,
+impl Hint {
+    const TAG_MASK: u64 = 0xffff_0000_0000_0000;
+    const TAG: u64 = 0xfffe_0000_0000_0000;
+
+    /// The cell holding the hint.
+    #[inline]
+    pub const fn cell(self) -> u64 {
+        Self::TAG | self as u64
+    }
+
+    #[inline]
+    pub fn from_cell(cell: u64) -> Option<Hint> {
+        if !Self::is_tagged(cell) {
+            return None;
+        }
+
+        match cell & !Self::TAG_MASK {
+            1 => Some(Hint::Return),
+            2 => Some(Hint::Break),
+            3 => Some(Hint::While),
+            _ => None,
+        }
+    }
+
+    /// Whether the cell is tagged as a hint, known or not.
+    #[inline]
+    pub fn is_tagged(cell: u64) -> bool {
+        cell & Self::TAG_MASK == Self::TAG
+    }
 }
 
-impl Value {
-    pub fn truthy(&self) -> bool {
-        match self {
-            Value::Nil => false,
-            Value::Boolean(b) => *b,
-            Value::Float(f) => *f == 1.0,
+/// The cell an instruction starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Hint(Hint),
+    Op(OpCode),
+}
+
+impl Instr {
+    /// Decodes the cell an instruction starts with, `resolve` maps the
+    /// cells of operations to their [`OpCode`], e.g. [`OpCode::from_id`] for
+    /// encoded tapes. Only [statements](Operand::Statement) can be hinted,
+    /// the operations evaluating them are the only ones reading hints.
+    pub fn decode(
+        cell: u64,
+        resolve: fn(u64) -> Option<OpCode>,
+        statement: bool,
+    ) -> Result<Instr, InstrError> {
+        match Hint::from_cell(cell) {
+            Some(hint @ (Hint::Return | Hint::While)) if statement => Ok(Instr::Hint(hint)),
+            Some(hint) => Err(InstrError::InvalidHint(hint)),
+            None if Hint::is_tagged(cell) => Err(InstrError::UnknownOp),
+            None => resolve(cell).map(Instr::Op).ok_or(InstrError::UnknownOp),
         }
     }
 }
 
+/// Why a cell can't start an instruction where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrError {
+    /// The cell is neither a hint nor an operation.
+    UnknownOp,
+    /// The hint doesn't precede a statement, or is never emitted.
+    InvalidHint(Hint),
+}
+
 /// WARNING! You have to be extremely careful when calling
 /// transmute on Operation, if T doesn't correspond to the
 /// actual T type it will cause segmentation faults.
@@ -34,11 +92,115 @@ impl Value {
 /// and discard the result.
 pub struct Operation<T = Value>(unsafe fn(&mut CallContext) -> T);
 
-impl<T> Operation<T> {
+/// What operations return, seen as a `Value` by execution hooks.
+pub trait OpResult {
+    fn as_value(&self) -> Option<Value>;
+
+    /// Returned by operations unwinding because the program halted, see
+    /// [`CallContext::halted`].
+    fn unwound() -> Self;
+}
+
+impl OpResult for Value {
+    fn as_value(&self) -> Option<Value> {
+        Some(*self)
+    }
+
+    fn unwound() -> Self {
+        Value::Nil
+    }
+}
+
+impl OpResult for Option<Value> {
+    fn as_value(&self) -> Option<Value> {
+        *self
+    }
+
+    fn unwound() -> Self {
+        None
+    }
+}
+
+impl OpResult for f64 {
+    fn as_value(&self) -> Option<Value> {
+        Some(Value::Float(*self))
+    }
+
+    fn unwound() -> Self {
+        0.0
+    }
+}
+
+impl<T: OpResult> Operation<T> {
     #[inline]
     pub unsafe fn call(self, ctx: &mut CallContext) -> T {
+        if !ctx.spend() {
+            return T::unwound();
+        }
+
+        #[cfg(any(feature = "debugger", feature = "tracing", feature = "profiler"))]
+        if ctx.hooked() {
+            return unsafe { self.call_hooked(ctx) };
+        }
+
         unsafe { self.0(ctx) }
     }
+
+    /// Runs the operation between the hooks of the attached debugger,
+    /// tracer and profiler.
+    #[cfg(any(feature = "debugger", feature = "tracing", feature = "profiler"))]
+    #[inline(never)]
+    unsafe fn call_hooked(self, ctx: &mut CallContext) -> T {
+        let offset = ctx.tape.offset - 1;
+
+        #[cfg(feature = "debugger")]
+        let debugger = ctx.debugger.clone();
+        #[cfg(feature = "debugger")]
+        if let Some(debugger) = &debugger {
+            debugger.borrow_mut().before(offset, &ctx.globals);
+        }
+
+        #[cfg(feature = "tracing")]
+        let tracing = ctx.tracing.clone();
+        #[cfg(feature = "tracing")]
+        if let Some(tracing) = &tracing {
+            tracing.borrow_mut().enter();
+        }
+
+        #[cfg(feature = "profiler")]
+        let profiler = ctx.profiler.clone();
+        #[cfg(feature = "profiler")]
+        if let Some(profiler) = &profiler {
+            let op = OpCode::from_address(self.0 as *const () as usize as u64);
+            profiler.borrow_mut().enter(offset, op);
+        }
+
+        let result = unsafe { self.0(ctx) };
+
+        #[cfg(feature = "profiler")]
+        if let Some(profiler) = &profiler {
+            profiler.borrow_mut().exit();
+        }
+
+        #[cfg(feature = "tracing")]
+        if let Some(tracing) = &tracing {
+            if ctx.halted() {
+                tracing.borrow_mut().unwind();
+            } else {
+                let op = OpCode::from_address(self.0 as *const () as usize as u64);
+                tracing
+                    .borrow_mut()
+                    .exit(offset, op, ctx.tape.cells(), result.as_value());
+            }
+        }
+
+        #[cfg(feature = "debugger")]
+        if let Some(debugger) = &debugger {
+            debugger.borrow_mut().after(offset, &ctx.globals);
+        }
+
+        result
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -128,6 +290,15 @@ impl Tape {
         self.tape = start.add(dest);
     }
 
+    /// Every cell of the tape.
+    ///
+    /// # Safety
+    ///
+    /// The tape must still be alive.
+    pub unsafe fn cells(&self) -> &[u64] {
+        std::slice::from_raw_parts(self.tape.sub(self.offset), self.size)
+    }
+
     pub unsafe fn debug(&mut self) {
         println!("Tape: {:?}", self.tape);
         let mut ptr = self.tape.sub(self.offset).clone();
@@ -148,6 +319,9 @@ impl Tape {
 pub struct ImCompiler {
     pub globals: Vec<String>,
     pub future_tape: Vec<u64>,
+    /// When set, operations on provably float operands are compiled to
+    /// their [`specialized`] variants.
+    pub types: Option<passes::TypeInfo>,
 }
 
 impl ImCompiler {
@@ -155,6 +329,16 @@ impl ImCompiler {
         Self {
             globals: Vec::new(),
             future_tape: Vec::new(),
+            types: None,
+        }
+    }
+
+    /// A compiler emitting specialized operations, `types` has to come
+    /// from [`passes::check_types`] on the compiled program.
+    pub fn with_types(types: passes::TypeInfo) -> Self {
+        Self {
+            types: Some(types),
+            ..Self::new()
         }
     }
 
@@ -175,6 +359,51 @@ impl ImCompiler {
         idx
     }
 
+    /// Runs the optimization passes over `expr` before compiling it, see
+    /// [`passes::optimize`].
+    pub fn compile_optimized(&mut self, expr: Expr, observable: &[&str]) {
+        self.compile_expr(passes::optimize(expr, observable));
+    }
+
+    /// Whether `expr` always evaluates to a float.
+    fn is_float(&self, expr: &Expr) -> bool {
+        let Some(types) = &self.types else {
+            return false;
+        };
+
+        match expr {
+            Expr::Float(_) => true,
+            Expr::Var(Binding::Global(name)) => types.global(name) == passes::Type::Float,
+            Expr::BinaryOp(lhs, op, rhs) => {
+                OpCode::raw(*op).is_some() && self.is_float(lhs) && self.is_float(rhs)
+            }
+            _ => false,
+        }
+    }
+
+    /// Compiles an expression for which [`Self::is_float`] holds to
+    /// operations returning `f64`.
+    fn compile_raw(&mut self, expr: Expr) {
+        match expr {
+            Expr::Float(x) => {
+                self.push(OpCode::RawFloat.address());
+                self.push(x.to_bits());
+            }
+            Expr::Var(Binding::Global(name)) => {
+                let idx = self.constant_get_or_def(name) as u64;
+
+                self.push(OpCode::RawVar.address());
+                self.push(idx);
+            }
+            Expr::BinaryOp(lhs, op, rhs) => {
+                self.push(OpCode::raw(op).unwrap().address());
+                self.compile_raw(*lhs);
+                self.compile_raw(*rhs);
+            }
+            expr => unreachable!("Not a float expression: {expr:?}"),
+        }
+    }
+
     pub fn compile_expr(&mut self, expr: Expr) {
         match expr {
             Expr::Boolean(b) => {
@@ -210,83 +439,95 @@ impl ImCompiler {
             },
 
             Expr::Return(value) => {
-                self.push(Hint::Return as u64);
+                self.push(Hint::Return.cell());
                 self.compile_expr(*value);
             }
 
-            Expr::Block(statements) => {
-                let instr_idx = self.future_tape.len();
-                self.push(0);
-                // self.push(unsafe { transmute(Operation(block) as Operation<Value>) });
+            Expr::Yield(value) => {
+                self.push(OpCode::Yield.address());
+                self.compile_expr(*value);
+            }
+
+            Expr::Coroutine(body) => {
+                self.push(OpCode::Coroutine.address());
+
                 let next_instr = self.future_tape.len();
                 self.push(0);
 
-                let (mut has_return, mut has_while) = (false, false);
+                self.compile_expr(*body);
 
-                for statement in statements {
-                    if let Expr::Return(_) = statement {
-                        has_return = true;
-                        self.compile_expr(statement);
-                        break;
-                    } else if let Expr::While(_, _) = statement {
-                        has_while = true;
-                    }
+                self.future_tape[next_instr] = self.future_tape.len() as u64;
+            }
 
-                    self.compile_expr(statement);
-                }
+            Expr::Resume(coroutine, value) => {
+                self.push(OpCode::Resume.address());
+                self.compile_expr(*coroutine);
+                self.compile_expr(*value);
+            }
 
-                self.future_tape[instr_idx] = if has_return || has_while {
-                    unsafe { transmute(Operation(flow::block_checked) as Operation<Value>) }
-                } else {
-                    unsafe { transmute(Operation(flow::block) as Operation<Value>) }
-                };
+            Expr::Done(coroutine) => {
+                self.push(OpCode::Done.address());
+                self.compile_expr(*coroutine);
+            }
 
-                // Explicitely fetching the next instruction's index avoids
-                // off by one errors
-                self.future_tape[next_instr] = self.future_tape.len() as u64;
+            Expr::Throw(exception) => {
+                self.push(OpCode::Throw.address());
+                self.compile_expr(*exception);
+            }
+
+            Expr::Try {
+                body,
+                catch_binding: Binding::Global(name),
+                handler,
+                finally,
+            } => {
+                self.push(OpCode::Try.address());
+
+                let handler_fix_idx = self.future_tape.len();
+                self.push(0);
+                let finally_fix_idx = self.future_tape.len();
+                self.push(0);
+
+                let idx = self.constant_get_or_def(name);
+                self.push(idx as u64);
+
+                self.compile_expr(*body);
+                self.future_tape[handler_fix_idx] = self.future_tape.len() as u64;
+                self.compile_expr(*handler);
+                self.future_tape[finally_fix_idx] = self.future_tape.len() as u64;
+                self.compile_expr(finally.map_or(Expr::Block(vec![]), |finally| *finally));
+            }
+
+            Expr::Block(statements) => {
+                let checked = statements.iter().any(returns);
+                self.compile_block(statements, checked);
             }
 
             Expr::While(cond, body) => {
-                self.push(Hint::While as u64);
+                self.push(Hint::While.cell());
                 self.push(unsafe {
-                    transmute(Operation(flow::while_loop) as Operation<Option<Value>>)
+                    transmute::<Operation<Option<Value>>, u64>(Operation(flow::while_loop))
                 });
 
                 let next_instr = self.future_tape.len();
                 self.push(0);
 
                 self.compile_expr(*cond);
-                self.compile_expr(*body);
+                self.compile_statement(*body);
 
                 // Explicitely fetching the next instruction's index avoids
                 // off by one errors
                 self.future_tape[next_instr] = self.future_tape.len() as u64;
             }
 
-            Expr::Conditional(true_t, elifs, else_body) => {
-                self.push(unsafe { transmute(Operation(flow::conditional) as Operation<Value>) });
-                let (true_cond, true_body) = *true_t;
-                self.push(1 + elifs.len() as u64);
-                let end_fix_idx = self.future_tape.len();
-                self.push(0);
-
-                let mut false_fix_idx = self.future_tape.len();
-                self.push(0);
-                self.compile_expr(true_cond);
-                self.compile_expr(true_body);
-
-                for (cond, body) in elifs {
-                    self.future_tape[false_fix_idx] = self.future_tape.len() as u64;
-                    false_fix_idx = self.future_tape.len();
-                    self.push(0);
-                    self.compile_expr(cond);
-                    self.compile_expr(body);
-                }
-
-                self.future_tape[false_fix_idx] = self.future_tape.len() as u64;
-                self.compile_expr(*else_body);
+            Expr::Conditional(first, elifs, else_body) => {
+                self.compile_conditional(*first, elifs, else_body, Self::compile_expr);
+            }
 
-                self.future_tape[end_fix_idx] = self.future_tape.len() as u64;
+            Expr::BinaryOp(lhs, op, rhs) if self.is_float(&lhs) && self.is_float(&rhs) => {
+                self.push(OpCode::specialized(op).address());
+                self.compile_raw(*lhs);
+                self.compile_raw(*rhs);
             }
 
             Expr::BinaryOp(lhs, op, rhs) => {
@@ -305,7 +546,7 @@ impl ImCompiler {
                 };
 
                 self.future_tape
-                    .push(unsafe { transmute(Operation(func) as Operation<Value>) });
+                    .push(unsafe { transmute::<Operation<Value>, u64>(Operation(func)) });
                 self.compile_expr(*lhs);
                 self.compile_expr(*rhs);
             }
@@ -313,6 +554,152 @@ impl ImCompiler {
             Expr::Add(_, _) => {}
         }
     }
+
+    /// Compiles a statement of a block, loop or conditional, blocks and
+    /// conditionals used as statements leave the `return` they reach to the
+    /// enclosing block.
+    fn compile_statement(&mut self, statement: Expr) {
+        match statement {
+            Expr::Block(statements) => self.compile_block(statements, false),
+            Expr::Conditional(first, elifs, else_body) => {
+                self.compile_conditional(*first, elifs, else_body, Self::compile_statement);
+            }
+            statement => self.compile_expr(statement),
+        }
+    }
+
+    /// Compiles a block, a `checked` one evaluates to the value of the
+    /// `return` its statements reach instead of returning from the
+    /// enclosing block.
+    fn compile_block(&mut self, statements: Vec<Expr>, checked: bool) {
+        self.push(if checked {
+            OpCode::BlockChecked.address()
+        } else {
+            OpCode::Block.address()
+        });
+        let next_instr = self.future_tape.len();
+        self.push(0);
+
+        for statement in statements {
+            let is_return = matches!(statement, Expr::Return(_));
+            self.compile_statement(statement);
+
+            if is_return {
+                break;
+            }
+        }
+
+        // Explicitely fetching the next instruction's index avoids
+        // off by one errors
+        self.future_tape[next_instr] = self.future_tape.len() as u64;
+    }
+
+    fn compile_conditional(
+        &mut self,
+        (first_cond, first_body): (Expr, Expr),
+        elifs: Vec<(Expr, Expr)>,
+        else_body: Option<Box<Expr>>,
+        compile_body: fn(&mut Self, Expr),
+    ) {
+        self.push(OpCode::Conditional.address());
+        self.push(1 + elifs.len() as u64);
+        let end_fix_idx = self.future_tape.len();
+        self.push(0);
+
+        let mut false_fix_idx = self.future_tape.len();
+        self.push(0);
+        self.compile_expr(first_cond);
+        compile_body(self, first_body);
+
+        for (cond, body) in elifs {
+            self.future_tape[false_fix_idx] = self.future_tape.len() as u64;
+            false_fix_idx = self.future_tape.len();
+            self.push(0);
+            self.compile_expr(cond);
+            compile_body(self, body);
+        }
+
+        self.future_tape[false_fix_idx] = self.future_tape.len() as u64;
+        // Without an `else` body, an empty block evaluates to `Nil`.
+        compile_body(
+            self,
+            else_body.map_or(Expr::Block(vec![]), |else_body| *else_body),
+        );
+
+        self.future_tape[end_fix_idx] = self.future_tape.len() as u64;
+    }
+}
+
+/// How a [run](CallContext::run) of a program ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Finished(Value),
+    /// The program used up its fuel, it carries on from where it stopped
+    /// once [refueled](CallContext::refuel) and run again.
+    OutOfFuel,
+    /// The program was stopped through an [`InterruptHandle`], it carries
+    /// on from where it stopped when run again.
+    Interrupted,
+    /// The program yielded a value, it carries on from where it stopped
+    /// once [resumed](CallContext::resume).
+    Yielded(Value),
+    /// The program threw an exception it didn't catch, runtime errors are
+    /// thrown as `Value::Error`. It can't be resumed.
+    Threw(Value),
+}
+
+/// Stops a program running on another thread, see
+/// [`CallContext::interrupt_handle`].
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Stops the program at its next loop iteration or block statement.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt is pending, it is cleared once it stopped the
+    /// program.
+    pub fn is_pending(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What an operation cut short by a halt needs to carry on when the
+/// program is resumed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// Offset of the operation.
+    pub offset: usize,
+    /// Offset it carries on from.
+    pub resume: usize,
+    /// Operand it already evaluated, or the value a `yield` is resumed
+    /// with.
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Not running, it starts or carries on from its last `yield` when
+    /// resumed.
+    Suspended,
+    Running,
+    Done,
+}
+
+/// A coroutine created by the program. Its state lives in the context
+/// rather than on the Rust stack: when it yields, the operations it ran
+/// unwind into `frames`, which are navigated back once it's resumed.
+#[derive(Debug, Clone)]
+pub struct Coroutine {
+    /// Offset of its body.
+    pub body: usize,
+    pub status: CoroutineStatus,
+    /// The operations it stopped in, see [`CallContext::continuation`].
+    frames: Vec<Frame>,
+    /// Offset the `resume` running it carries on from.
+    caller: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -320,6 +707,22 @@ pub struct CallContext {
     pub tape: Tape,
     stack: Vec<Value>,
     pub globals: Vec<Value>,
+    /// Operations left to run, `None` when unlimited.
+    fuel: Option<u64>,
+    /// Why the program is unwinding, if it is.
+    halt: Option<Outcome>,
+    /// Value of the `return` being unwound up to the closest checked block.
+    returned: Option<Value>,
+    /// Operations that unwound, outermost last.
+    frames: Vec<Frame>,
+    interrupt: Arc<AtomicBool>,
+    coroutines: Vec<Coroutine>,
+    #[cfg(feature = "debugger")]
+    pub debugger: Option<std::rc::Rc<std::cell::RefCell<Debugger>>>,
+    #[cfg(feature = "tracing")]
+    pub tracing: Option<std::rc::Rc<std::cell::RefCell<Tracing>>>,
+    #[cfg(feature = "profiler")]
+    pub profiler: Option<std::rc::Rc<std::cell::RefCell<Profiler>>>,
 }
 
 impl CallContext {
@@ -328,12 +731,336 @@ impl CallContext {
             tape: Tape::new(tape, size),
             stack: Vec::new(),
             globals: vec![Value::Float(0.0); globals_amt],
+            fuel: None,
+            halt: None,
+            returned: None,
+            frames: Vec::new(),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            coroutines: Vec::new(),
+            #[cfg(feature = "debugger")]
+            debugger: None,
+            #[cfg(feature = "tracing")]
+            tracing: None,
+            #[cfg(feature = "profiler")]
+            profiler: None,
         }
     }
 
+    /// Whether a debugger, tracer or profiler is attached.
+    #[cfg(any(feature = "debugger", feature = "tracing", feature = "profiler"))]
+    #[inline]
+    fn hooked(&self) -> bool {
+        #[cfg(feature = "debugger")]
+        if self.debugger.is_some() {
+            return true;
+        }
+
+        #[cfg(feature = "tracing")]
+        if self.tracing.is_some() {
+            return true;
+        }
+
+        #[cfg(feature = "profiler")]
+        if self.profiler.is_some() {
+            return true;
+        }
+
+        false
+    }
+
     pub fn execute(&mut self) -> Value {
         unsafe { self.tape.get_next_func::<Value>().call(self) }
     }
+
+    /// Runs the program from the start, or from where it stopped if it
+    /// halted. A `yield` it stopped at evaluates to `Nil`.
+    pub fn run(&mut self) -> Outcome {
+        self.halt = None;
+        self.returned = None;
+
+        unsafe { self.tape.move_to(0) };
+        let value = self.execute();
+
+        match self.halt {
+            Some(Outcome::Threw(exception)) => {
+                self.frames.clear();
+                Outcome::Threw(exception)
+            }
+            Some(halt) => halt,
+            None => Outcome::Finished(value),
+        }
+    }
+
+    /// Carries on with a program that yielded, the `yield` evaluating to
+    /// `value`. Programs that halted for other reasons ignore `value`.
+    pub fn resume(&mut self, value: Value) -> Outcome {
+        if let (Some(Outcome::Yielded(_)), Some(frame)) = (self.halt, self.frames.first_mut()) {
+            frame.value = Some(value);
+        }
+
+        self.run()
+    }
+
+    /// Whether the program halted before finishing, its continuation is
+    /// kept in the context until it's run again.
+    pub fn is_suspended(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// The operations the program halted in, innermost first.
+    pub fn continuation(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Operations left to run, `None` when unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Limits the program to `fuel` more operations and loop iterations,
+    /// `None` lifts the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Gives the program `fuel` more operations to run.
+    pub fn refuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    /// Uses up one unit of fuel, halting the program when there is none
+    /// left. Operations being resumed run for free.
+    #[inline]
+    pub fn spend(&mut self) -> bool {
+        match &mut self.fuel {
+            // Only metered with a budget, this runs for every operation.
+            None => true,
+            _ if !self.frames.is_empty() => true,
+            Some(0) => {
+                self.halt = Some(Outcome::OutOfFuel);
+                false
+            }
+            Some(fuel) => {
+                *fuel -= 1;
+                true
+            }
+        }
+    }
+
+    /// A handle stopping the program from any thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupt.clone())
+    }
+
+    /// Halts the program if it was interrupted, called by loops and blocks
+    /// before every iteration.
+    #[inline]
+    pub fn interrupted(&mut self) -> bool {
+        if self.interrupt.load(Ordering::Relaxed)
+            && self.frames.is_empty()
+            && self.interrupt.swap(false, Ordering::Relaxed)
+        {
+            self.halt = Some(Outcome::Interrupted);
+            return true;
+        }
+
+        false
+    }
+
+    /// Halts the program, see [`Self::halted`].
+    pub fn halt(&mut self, outcome: Outcome) {
+        self.halt = Some(outcome);
+    }
+
+    /// Whether the program halted, operations have to return right away
+    /// after [saving](Self::suspend) what they need to carry on.
+    #[inline]
+    pub fn halted(&self) -> bool {
+        self.halt.is_some()
+    }
+
+    /// Makes the statement being run return `value` from the block it's in
+    /// once it finished.
+    pub fn return_with(&mut self, value: Value) {
+        self.returned = Some(value);
+    }
+
+    /// The value the last statement returned from its block, see
+    /// [`Self::return_with`].
+    #[inline]
+    pub fn take_return(&mut self) -> Option<Value> {
+        self.returned.take()
+    }
+
+    /// Throws `exception`, unwinding the program up to the closest `try`.
+    pub fn throw(&mut self, exception: Value) {
+        self.halt = Some(Outcome::Threw(exception));
+    }
+
+    /// Whether the program is unwinding because of an exception.
+    #[inline]
+    pub fn is_throwing(&self) -> bool {
+        matches!(self.halt, Some(Outcome::Threw(_)))
+    }
+
+    /// Stops the exception being thrown, if any, dropping what the
+    /// operations it unwound saved.
+    pub fn catch(&mut self) -> Option<Value> {
+        match self.halt {
+            Some(Outcome::Threw(exception)) => {
+                self.halt = None;
+                self.returned = None;
+                self.frames.clear();
+                Some(exception)
+            }
+            _ => None,
+        }
+    }
+
+    /// Gives up on a halted program, dropping what the operations it
+    /// unwound saved so it runs from the start next time. Returns why it
+    /// halted.
+    pub fn abandon(&mut self) -> Option<Outcome> {
+        let halt = self.halt.take()?;
+        self.returned = None;
+        self.frames.clear();
+
+        Some(halt)
+    }
+
+    /// Saves the state of the operation at `offset` unwinding because of a
+    /// halt.
+    pub fn suspend(&mut self, offset: usize, resume: usize, value: Option<Value>) {
+        self.frames.push(Frame {
+            offset,
+            resume,
+            value,
+        });
+    }
+
+    /// The state saved by the operation at `offset` if the program is
+    /// resuming it, operations with operands have to check for it first.
+    #[inline]
+    pub fn resumed(&mut self, offset: usize) -> Option<Frame> {
+        match self.frames.last() {
+            Some(frame) if frame.offset == offset => self.frames.pop(),
+            _ => None,
+        }
+    }
+
+    /// Creates a coroutine running the body at `body`, returning its handle.
+    pub fn spawn(&mut self, body: usize) -> Value {
+        self.coroutines.push(Coroutine {
+            body,
+            status: CoroutineStatus::Suspended,
+            frames: Vec::new(),
+            caller: 0,
+        });
+
+        Value::Coroutine(self.coroutines.len() as u32 - 1)
+    }
+
+    /// The coroutine `handle` refers to.
+    pub fn coroutine(&self, handle: Value) -> Option<&Coroutine> {
+        self.coroutines.get(handle.as_coroutine()? as usize)
+    }
+
+    /// Runs the coroutine `handle` for the `resume` at `offset` until it
+    /// yields or finishes. The `yield` it stopped at evaluates to `sent`,
+    /// `None` carries on with a coroutine the program halted in.
+    ///
+    /// # Safety
+    ///
+    /// The coroutine's body must be on the tape being run.
+    pub unsafe fn run_coroutine(
+        &mut self,
+        offset: usize,
+        handle: Value,
+        sent: Option<Value>,
+    ) -> Value {
+        let idx = match handle.as_coroutine() {
+            Some(idx) if (idx as usize) < self.coroutines.len() => idx as usize,
+            _ => {
+                self.throw(Value::Error(RuntimeError::NotACoroutine));
+                return Value::Nil;
+            }
+        };
+        let coroutine = &mut self.coroutines[idx];
+
+        if let Some(sent) = sent {
+            match coroutine.status {
+                CoroutineStatus::Suspended => {}
+                CoroutineStatus::Running => {
+                    self.throw(Value::Error(RuntimeError::CoroutineRunning));
+                    return Value::Nil;
+                }
+                CoroutineStatus::Done => return Value::Nil,
+            }
+
+            coroutine.caller = self.tape.offset;
+
+            if let Some(frame) = coroutine.frames.first_mut() {
+                frame.value = Some(sent);
+            }
+        }
+
+        // The caller isn't being resumed anymore, the frames left are the
+        // coroutine's.
+        coroutine.status = CoroutineStatus::Running;
+        std::mem::swap(&mut self.frames, &mut coroutine.frames);
+
+        self.tape.move_to(coroutine.body);
+        let value = self.tape.get_next_func::<Value>().call(self);
+        let value = self.take_return().unwrap_or(value);
+
+        let coroutine = &mut self.coroutines[idx];
+        std::mem::swap(&mut self.frames, &mut coroutine.frames);
+
+        match self.halt {
+            Some(Outcome::Yielded(value)) => {
+                self.halt = None;
+                coroutine.status = CoroutineStatus::Suspended;
+                self.tape.move_to(coroutine.caller);
+
+                value
+            }
+            // A coroutine that threw can't carry on.
+            Some(Outcome::Threw(_)) => {
+                coroutine.status = CoroutineStatus::Done;
+                coroutine.frames.clear();
+                Value::Nil
+            }
+            Some(_) => {
+                self.suspend(offset, offset, Some(handle));
+                Value::Nil
+            }
+            None => {
+                coroutine.status = CoroutineStatus::Done;
+                self.tape.move_to(coroutine.caller);
+
+                value
+            }
+        }
+    }
+}
+
+/// Whether a statement can return from the block it's in, from any depth
+/// of the blocks, loops and conditionals it's made of.
+fn returns(statement: &Expr) -> bool {
+    match statement {
+        Expr::Return(_) => true,
+        Expr::Block(statements) => statements.iter().any(returns),
+        Expr::While(_, body) => returns(body),
+        Expr::Conditional(first, elifs, else_body) => {
+            std::iter::once(&**first)
+                .chain(elifs)
+                .any(|(_, body)| returns(body))
+                || else_body.as_deref().is_some_and(returns)
+        }
+        _ => false,
+    }
 }
 
 // TODO: (currently) Doesn't ensure that the vector doesn't get dropped!
@@ -454,6 +1181,87 @@ pub fn tape_test() {
     assert_eq!(context.globals[0], Value::Float(0.0));
 }
 
+#[test]
+pub fn specialized() {
+    let x = || Binding::Global("x".into());
+    let expr = Expr::Block(vec![
+        x().assign(Expr::Float(1000.25)),
+        Expr::While(
+            x().var().op(Operator::Gt, Expr::Float(0.0)).into(),
+            x().assign(x().var().op(
+                Operator::Sub,
+                Expr::Float(2.0).op(Operator::Div, Expr::Float(4.0)),
+            ))
+            .into(),
+        ),
+    ]);
+
+    let types = passes::check_types(&expr).unwrap();
+    let mut compiler = ImCompiler::with_types(types);
+    compiler.compile_expr(expr);
+
+    let ops = compiler
+        .future_tape
+        .iter()
+        .filter_map(|cell| OpCode::from_address(*cell))
+        .collect::<Vec<_>>();
+
+    assert!(ops.contains(&OpCode::F64Gt));
+    assert!(ops.contains(&OpCode::F64Sub));
+    assert!(ops.contains(&OpCode::RawDiv));
+    assert!(!ops.contains(&OpCode::Var));
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.execute();
+
+    assert_eq!(context.globals[0], Value::Float(-0.25));
+}
+
+/// Runs `program` compiled with and without types, before and after the
+/// peephole pass, with every fuel budget up to `steps` (0 for none),
+/// refueling until it stops. `check` gets each context and its outcome.
+#[cfg(test)]
+fn run_metered(program: &Expr, steps: u64, check: impl Fn(&CallContext, Outcome)) {
+    let types = passes::check_types(program).unwrap();
+
+    for mut compiler in [ImCompiler::new(), ImCompiler::with_types(types)] {
+        compiler.compile_expr(program.clone());
+
+        assert_eq!(compiler.decompile(), Ok(program.clone()));
+        assert_eq!(compiler.verify(), Ok(()));
+
+        let mut optimized = compiler.clone();
+        optimized.peephole().unwrap();
+
+        for compiler in [&compiler, &optimized] {
+            for step in 0..=steps {
+                let mut context = CallContext::new(
+                    compiler.future_tape.as_ptr(),
+                    compiler.future_tape.len(),
+                    compiler.globals.len(),
+                );
+
+                if step > 0 {
+                    context.set_fuel(Some(step));
+                }
+
+                let mut outcome = context.run();
+
+                while outcome == Outcome::OutOfFuel {
+                    context.refuel(step);
+                    outcome = context.run();
+                }
+
+                check(&context, outcome);
+            }
+        }
+    }
+}
+
 #[test]
 pub fn conditional() {
     let program = Expr::Conditional(
@@ -464,12 +1272,11 @@ pub fn conditional() {
             (Expr::Boolean(true), Expr::Float(7.0)),
             (Expr::Boolean(false), Expr::Float(3.0)),
         ],
-        Expr::Float(5.0).into(),
+        Some(Expr::Float(5.0).into()),
     );
 
     let mut compiler = ImCompiler::new();
     compiler.compile_expr(program);
-    println!("Compiler: {compiler:?}");
 
     let mut context = CallContext::new(
         compiler.future_tape.as_ptr(),
@@ -477,9 +1284,638 @@ pub fn conditional() {
         compiler.globals.len(),
     );
 
-    let end_value = context.execute();
+    assert_eq!(context.execute(), Value::Float(7.0));
+
+    let global = |name: &str| Binding::Global(name.into());
+    let var = |name: &str| Expr::global(name);
+
+    let program = Expr::Block(vec![
+        global("i").assign(Expr::Float(0.0)),
+        global("odd").assign(Expr::Float(0.0)),
+        // Used as a value, and without an `else` body.
+        global("none").assign(Expr::Conditional(
+            (Expr::Boolean(false), Expr::Float(1.0)).into(),
+            vec![],
+            None,
+        )),
+        // Returns out of the loop and the enclosing block.
+        Expr::While(
+            Expr::Boolean(true).into(),
+            Expr::Conditional(
+                (
+                    var("i").op(Operator::Eq, Expr::Float(5.0)),
+                    Expr::Return(var("odd").op(Operator::Mul, Expr::Float(10.0)).into()),
+                )
+                    .into(),
+                vec![],
+                Some(
+                    Expr::Block(vec![
+                        global("i").assign(var("i").op(Operator::Add, Expr::Float(1.0))),
+                        global("odd").assign(
+                            var("odd").op(
+                                Operator::Add,
+                                Expr::Conditional(
+                                    (
+                                        var("i").op(Operator::Rem, Expr::Float(2.0)),
+                                        Expr::Float(1.0),
+                                    )
+                                        .into(),
+                                    vec![],
+                                    Some(Expr::Float(0.0).into()),
+                                ),
+                            ),
+                        ),
+                    ])
+                    .into(),
+                ),
+            )
+            .into(),
+        ),
+        Expr::Return(Expr::Float(-1.0).into()),
+    ]);
+
+    run_metered(&program, 20, |context, outcome| {
+        assert_eq!(outcome, Outcome::Finished(Value::Float(30.0)));
+        assert_eq!(context.globals[2], Value::Nil);
+    });
+}
+
+#[test]
+pub fn nested_returns() {
+    let global = |name: &str| Binding::Global(name.into());
+    let var = |name: &str| Expr::global(name);
+    let add = |name: &str, value: Expr| global(name).assign(var(name).op(Operator::Add, value));
+
+    let program = Expr::Block(vec![
+        // A block used as a value stops the return.
+        global("caught").assign(Expr::Block(vec![
+            Expr::While(
+                Expr::Boolean(true).into(),
+                Expr::Block(vec![Expr::Conditional(
+                    (Expr::Boolean(true), Expr::Return(Expr::Float(1.0).into())).into(),
+                    vec![],
+                    None,
+                )])
+                .into(),
+            ),
+            Expr::Return(Expr::Float(2.0).into()),
+        ])),
+        // The coroutine returns from its body, finishing.
+        global("k").assign(Expr::Float(0.0)),
+        global("gen").assign(Expr::Coroutine(
+            Expr::Block(vec![Expr::While(
+                Expr::Boolean(true).into(),
+                Expr::Block(vec![
+                    add("k", Expr::Float(1.0)),
+                    Expr::Conditional(
+                        (
+                            var("k").op(Operator::Eq, Expr::Float(3.0)),
+                            Expr::Return(var("k").op(Operator::Mul, Expr::Float(10.0)).into()),
+                        )
+                            .into(),
+                        vec![],
+                        None,
+                    ),
+                    Expr::Yield(var("k").into()),
+                ])
+                .into(),
+            )])
+            .into(),
+        )),
+        global("total").assign(Expr::Float(0.0)),
+        Expr::While(
+            Expr::Done(var("gen").into())
+                .op(Operator::Eq, Expr::Boolean(false))
+                .into(),
+            add(
+                "total",
+                Expr::Resume(var("gen").into(), Expr::Boolean(true).into()),
+            )
+            .into(),
+        ),
+        // Returns the first `i * 100 + j` with `i * j > 20` from both loops.
+        global("i").assign(Expr::Float(0.0)),
+        Expr::While(
+            var("i").op(Operator::Lt, Expr::Float(10.0)).into(),
+            Expr::Block(vec![
+                add("i", Expr::Float(1.0)),
+                global("j").assign(Expr::Float(0.0)),
+                Expr::While(
+                    var("j").op(Operator::Lt, var("i")).into(),
+                    Expr::Block(vec![
+                        add("j", Expr::Float(1.0)),
+                        Expr::Conditional(
+                            (
+                                var("i")
+                                    .op(Operator::Mul, var("j"))
+                                    .op(Operator::Gt, Expr::Float(20.0)),
+                                Expr::Block(vec![Expr::Return(
+                                    var("i")
+                                        .op(Operator::Mul, Expr::Float(100.0))
+                                        .op(Operator::Add, var("j"))
+                                        .into(),
+                                )]),
+                            )
+                                .into(),
+                            vec![],
+                            None,
+                        ),
+                    ])
+                    .into(),
+                ),
+            ])
+            .into(),
+        ),
+        Expr::Return(Expr::Float(-1.0).into()),
+    ]);
+
+    run_metered(&program, 30, |context, outcome| {
+        assert_eq!(outcome, Outcome::Finished(Value::Float(505.0)));
+        assert_eq!(context.globals[0], Value::Float(1.0));
+        assert_eq!(context.globals[3], Value::Float(33.0));
+        assert_eq!(
+            context.coroutine(context.globals[2]).unwrap().status,
+            CoroutineStatus::Done
+        );
+    });
+}
+
+#[test]
+pub fn fuel() {
+    let spin = Expr::Block(vec![Expr::While(
+        Expr::Boolean(true).into(),
+        Expr::Block(vec![]).into(),
+    )]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(spin);
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.set_fuel(Some(1000));
+
+    assert_eq!(context.run(), Outcome::OutOfFuel);
+    assert_eq!(context.fuel(), Some(0));
+
+    context.refuel(10);
+    assert_eq!(context.run(), Outcome::OutOfFuel);
+
+    let x = || Binding::Global("x".into());
+    let y = || Binding::Global("y".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::Block(vec![y().assign(Expr::Float(2.0))]),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(5.0)).into(),
+            Expr::Block(vec![
+                x().assign(x().var().op(Operator::Add, Expr::Float(1.0))),
+                y().assign(Expr::Block(vec![Expr::Return(
+                    y().var()
+                        .op(Operator::Mul, Expr::Float(2.0))
+                        .op(Operator::Sub, x().var())
+                        .into(),
+                )])),
+            ])
+            .into(),
+        ),
+        Expr::Return(x().var().op(Operator::Add, y().var()).into()),
+    ]);
+
+    run_metered(&program, 25, |context, outcome| {
+        assert_eq!(outcome, Outcome::Finished(Value::Float(12.0)));
+        assert_eq!(context.globals, vec![Value::Float(5.0), Value::Float(7.0)]);
+    });
+}
+
+#[test]
+pub fn interrupt() {
+    let x = || Binding::Global("x".into());
+
+    let spin = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            Expr::Boolean(true).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(spin);
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    let handle = context.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        handle.interrupt();
+    });
+
+    assert_eq!(context.run(), Outcome::Interrupted);
+    interrupter.join().unwrap();
+
+    let handle = context.interrupt_handle();
+    assert!(!handle.is_pending());
+
+    let count = context.globals[0].as_float().unwrap();
+    assert!(count > 0.0);
+
+    let handle = context.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        handle.interrupt();
+    });
+
+    assert_eq!(context.run(), Outcome::Interrupted);
+    interrupter.join().unwrap();
+
+    assert!(context.globals[0].as_float().unwrap() > count);
+
+    // Interrupting before running stops the program right away, it then
+    // carries on from there.
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(1.0)),
+        x().assign(x().var().op(Operator::Mul, Expr::Float(3.0))),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    context.interrupt_handle().interrupt();
+    assert_eq!(context.run(), Outcome::Interrupted);
+    assert_eq!(context.globals, vec![Value::Float(0.0)]);
+
+    assert_eq!(context.run(), Outcome::Finished(Value::Nil));
+    assert_eq!(context.globals, vec![Value::Float(3.0)]);
+}
+
+#[test]
+pub fn suspend() {
+    let total = || Binding::Global("total".into());
+
+    let program = Expr::Block(vec![
+        total().assign(Expr::Float(0.0)),
+        Expr::While(
+            total().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            total()
+                .assign(
+                    total()
+                        .var()
+                        .op(Operator::Add, Expr::Yield(total().var().into())),
+                )
+                .into(),
+        ),
+        Expr::Return(total().var().op(Operator::Mul, Expr::Float(2.0)).into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program.clone());
+
+    assert_eq!(compiler.decompile(), Ok(program));
+    assert_eq!(compiler.verify(), Ok(()));
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    assert_eq!(context.run(), Outcome::Yielded(Value::Float(0.0)));
+    assert!(context.is_suspended());
+    assert_eq!(
+        context.resume(Value::Float(4.0)),
+        Outcome::Yielded(Value::Float(4.0))
+    );
+
+    // The continuation lives in the context, so a copy carries on
+    // independently.
+    let mut fork = context.clone();
+
+    assert_eq!(
+        context.resume(Value::Float(4.0)),
+        Outcome::Yielded(Value::Float(8.0))
+    );
+    assert_eq!(
+        context.resume(Value::Float(5.0)),
+        Outcome::Finished(Value::Float(26.0))
+    );
+    assert!(!context.is_suspended());
+
+    assert_eq!(
+        fork.resume(Value::Float(10.0)),
+        Outcome::Finished(Value::Float(28.0))
+    );
+
+    // Running out of fuel while yielding doesn't lose the value sent back.
+    for step in 1..=12 {
+        let mut context = CallContext::new(
+            compiler.future_tape.as_ptr(),
+            compiler.future_tape.len(),
+            compiler.globals.len(),
+        );
+        context.set_fuel(Some(step));
+
+        let mut yielded = Vec::new();
+        let mut outcome = context.run();
+
+        let result = loop {
+            outcome = match outcome {
+                Outcome::OutOfFuel => {
+                    context.refuel(step);
+                    context.run()
+                }
+                Outcome::Yielded(value) => {
+                    yielded.push(value);
+                    context.resume(Value::Float(3.0))
+                }
+                Outcome::Finished(value) => break value,
+                Outcome::Interrupted | Outcome::Threw(_) => unreachable!(),
+            };
+        };
+
+        assert_eq!(result, Value::Float(24.0));
+        assert_eq!(yielded, [0.0, 3.0, 6.0, 9.0].map(Value::Float).to_vec());
+    }
+}
+
+#[test]
+pub fn coroutines() {
+    let global = |name: &str| Binding::Global(name.into());
+    let var = |name: &str| Expr::global(name);
+
+    let program = Expr::Block(vec![
+        // A generator of 0, 10 and 20, finishing with -1.
+        global("gen").assign(Expr::Coroutine(
+            Expr::Block(vec![
+                global("i").assign(Expr::Float(0.0)),
+                Expr::While(
+                    var("i").op(Operator::Lt, Expr::Float(3.0)).into(),
+                    Expr::Block(vec![
+                        Expr::Yield(var("i").op(Operator::Mul, Expr::Float(10.0)).into()),
+                        global("i").assign(var("i").op(Operator::Add, Expr::Float(1.0))),
+                    ])
+                    .into(),
+                ),
+                Expr::Return(Expr::Float(-1.0).into()),
+            ])
+            .into(),
+        )),
+        global("sum").assign(Expr::Float(0.0)),
+        Expr::While(
+            Expr::Done(var("gen").into())
+                .op(Operator::Eq, Expr::Boolean(false))
+                .into(),
+            global("sum")
+                .assign(var("sum").op(
+                    Operator::Add,
+                    Expr::Resume(var("gen").into(), Expr::Float(0.0).into()),
+                ))
+                .into(),
+        ),
+        global("after").assign(Expr::Resume(var("gen").into(), Expr::Float(0.0).into())),
+        // Adds up the values it's resumed with.
+        global("acc").assign(Expr::Coroutine(
+            Expr::Block(vec![
+                global("total").assign(Expr::Float(0.0)),
+                Expr::While(
+                    Expr::Boolean(true).into(),
+                    global("total")
+                        .assign(var("total").op(Operator::Add, Expr::Yield(var("total").into())))
+                        .into(),
+                ),
+            ])
+            .into(),
+        )),
+        Expr::Resume(var("acc").into(), Expr::Float(1.0).into()),
+        Expr::Resume(var("acc").into(), Expr::Float(5.0).into()),
+        Expr::Return(
+            var("sum")
+                .op(Operator::Mul, Expr::Float(100.0))
+                .op(
+                    Operator::Add,
+                    Expr::Resume(var("acc").into(), Expr::Float(7.0).into()),
+                )
+                .into(),
+        ),
+    ]);
+
+    let types = passes::check_types(&program).unwrap();
+
+    for mut compiler in [ImCompiler::new(), ImCompiler::with_types(types)] {
+        compiler.compile_expr(program.clone());
+
+        assert_eq!(compiler.decompile(), Ok(program.clone()));
+        assert_eq!(compiler.verify(), Ok(()));
+
+        let global = |name: &str| compiler.globals.iter().position(|n| n == name).unwrap();
+
+        let mut context = CallContext::new(
+            compiler.future_tape.as_ptr(),
+            compiler.future_tape.len(),
+            compiler.globals.len(),
+        );
+
+        // 0 + 10 + 20 - 1, then the first value sent to `acc` is ignored.
+        assert_eq!(context.run(), Outcome::Finished(Value::Float(2912.0)));
+        assert_eq!(context.globals[global("after")], Value::Nil);
+
+        let gen = context.coroutine(context.globals[global("gen")]).unwrap();
+        assert_eq!(gen.status, CoroutineStatus::Done);
+        let acc = context.coroutine(context.globals[global("acc")]).unwrap();
+        assert_eq!(acc.status, CoroutineStatus::Suspended);
+
+        // Halting in the middle of a coroutine carries on with it.
+        for step in 1..=40 {
+            let mut context = CallContext::new(
+                compiler.future_tape.as_ptr(),
+                compiler.future_tape.len(),
+                compiler.globals.len(),
+            );
+            context.set_fuel(Some(step));
+
+            let mut outcome = context.run();
 
-    println!("Value: {end_value:?}");
+            while outcome == Outcome::OutOfFuel {
+                context.refuel(step);
+                outcome = context.run();
+            }
 
-    // assert_eq!(end_value, Value::Float(10.0));
+            assert_eq!(outcome, Outcome::Finished(Value::Float(2912.0)));
+        }
+    }
+}
+
+#[test]
+pub fn exceptions() {
+    let global = |name: &str| Binding::Global(name.into());
+    let var = |name: &str| Expr::global(name);
+    let try_catch = |body: Expr, binding: &str, handler: Expr, finally: Option<Expr>| Expr::Try {
+        body: body.into(),
+        catch_binding: global(binding),
+        handler: handler.into(),
+        finally: finally.map(Box::new),
+    };
+    let count =
+        |amount: f64| global("log").assign(var("log").op(Operator::Add, Expr::Float(amount)));
+
+    let program = Expr::Block(vec![
+        global("log").assign(Expr::Float(0.0)),
+        global("i").assign(Expr::Float(0.0)),
+        // Unwinds through both loops and their blocks.
+        global("r").assign(try_catch(
+            Expr::Block(vec![
+                Expr::While(
+                    Expr::Boolean(true).into(),
+                    Expr::Block(vec![
+                        global("i").assign(var("i").op(Operator::Add, Expr::Float(1.0))),
+                        Expr::While(
+                            var("i").op(Operator::Eq, Expr::Float(3.0)).into(),
+                            Expr::Throw(var("i").op(Operator::Mul, Expr::Float(100.0)).into())
+                                .into(),
+                        ),
+                    ])
+                    .into(),
+                ),
+                count(1000.0),
+            ]),
+            "e",
+            var("e").op(Operator::Add, Expr::Float(1.0)),
+            Some(count(1.0)),
+        )),
+        global("bad").assign(try_catch(
+            Expr::Boolean(true).op(Operator::Add, Expr::Float(1.0)),
+            "err",
+            var("err"),
+            None,
+        )),
+        // The handler rethrows, the `finally` body still runs.
+        global("nested").assign(try_catch(
+            try_catch(
+                Expr::Throw(Expr::Float(5.0).into()),
+                "e",
+                Expr::Throw(var("e").op(Operator::Mul, Expr::Float(2.0)).into()),
+                Some(count(10.0)),
+            ),
+            "e",
+            var("e"),
+            None,
+        )),
+        // Exceptions leave coroutines through the `resume` running them.
+        global("co").assign(Expr::Coroutine(
+            Expr::Block(vec![
+                Expr::Yield(Expr::Float(1.0).into()),
+                Expr::Throw(Expr::Float(7.0).into()),
+            ])
+            .into(),
+        )),
+        global("c").assign(try_catch(
+            Expr::Resume(var("co").into(), Expr::Float(0.0).into()).op(
+                Operator::Add,
+                Expr::Resume(var("co").into(), Expr::Float(0.0).into()),
+            ),
+            "e",
+            var("e"),
+            None,
+        )),
+        Expr::Return(
+            var("r")
+                .op(
+                    Operator::Add,
+                    var("log").op(Operator::Mul, Expr::Float(1000.0)),
+                )
+                .into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program.clone());
+
+    assert_eq!(compiler.decompile(), Ok(program));
+    assert_eq!(compiler.verify(), Ok(()));
+
+    let global = |name: &str| compiler.globals.iter().position(|n| n == name).unwrap();
+
+    for step in 0..=40 {
+        let mut context = CallContext::new(
+            compiler.future_tape.as_ptr(),
+            compiler.future_tape.len(),
+            compiler.globals.len(),
+        );
+
+        // Halting anywhere, `try`s included, doesn't change the result.
+        if step > 0 {
+            context.set_fuel(Some(step));
+        }
+
+        let mut outcome = context.run();
+
+        while outcome == Outcome::OutOfFuel {
+            context.refuel(step);
+            outcome = context.run();
+        }
+
+        assert_eq!(outcome, Outcome::Finished(Value::Float(11301.0)));
+        assert_eq!(
+            context.globals[global("bad")],
+            Value::Error(RuntimeError::InvalidOperands)
+        );
+        assert_eq!(context.globals[global("nested")], Value::Float(10.0));
+        assert_eq!(context.globals[global("c")], Value::Float(7.0));
+
+        let co = context.coroutine(context.globals[global("co")]).unwrap();
+        assert_eq!(co.status, CoroutineStatus::Done);
+    }
+
+    // Uncaught exceptions surface to the host and can't be resumed.
+    let x = || Binding::Global("x".into());
+
+    for (program, exception) in [
+        (
+            Expr::Block(vec![
+                x().assign(Expr::Float(1.0)),
+                Expr::Throw(x().var().op(Operator::Add, Expr::Float(1.0)).into()),
+                x().assign(Expr::Float(5.0)),
+            ]),
+            Value::Float(2.0),
+        ),
+        (
+            Expr::Block(vec![Expr::Return(
+                Expr::Boolean(true)
+                    .op(Operator::Mul, Expr::Float(2.0))
+                    .into(),
+            )]),
+            Value::Error(RuntimeError::InvalidOperands),
+        ),
+    ] {
+        let mut compiler = ImCompiler::new();
+        compiler.compile_expr(program);
+
+        let mut context = CallContext::new(
+            compiler.future_tape.as_ptr(),
+            compiler.future_tape.len(),
+            compiler.globals.len(),
+        );
+
+        assert_eq!(context.run(), Outcome::Threw(exception));
+        assert!(!context.is_suspended());
+    }
+
+    assert_eq!(
+        RuntimeError::InvalidOperands.to_string(),
+        "invalid operands"
+    );
 }
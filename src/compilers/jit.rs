@@ -0,0 +1,771 @@
+//! Baseline JIT for Linux x86-64.
+//!
+//! Float literals, float globals, arithmetic, comparisons, blocks and while
+//! loops are stitched together from machine code templates. Every other
+//! statement is run by calling back into the tape.
+//!
+//! Globals that provably always hold floats live unboxed in a slot array
+//! while the JIT'd code runs; they're written back to the `CallContext`
+//! around every call into the tape.
+//!
+//! JIT'd programs can't be suspended. Only what runs on the tape spends
+//! fuel and checks for interrupts, and a halt there (running out of fuel,
+//! an interrupt, a `yield` outside of a coroutine or an exception nothing
+//! catches) stops the program for good, see [`JitProgram::run`].
+
+use std::ffi::c_void;
+use std::ptr;
+
+use crate::expr::Operator;
+use crate::*;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+/// State shared between the JIT'd code and the helpers it calls.
+#[repr(C)]
+struct Frame {
+    ctx: *mut CallContext,
+    slots: *mut f64,
+    float_globals: *const Vec<usize>,
+    result: Value,
+}
+
+const FRAME_SLOTS: u8 = 8;
+
+pub struct JitProgram {
+    code: *mut c_void,
+    code_len: usize,
+    tape: Vec<u64>,
+    globals: usize,
+    float_globals: Vec<usize>,
+}
+
+impl Drop for JitProgram {
+    fn drop(&mut self) {
+        unsafe { munmap(self.code, self.code_len) };
+    }
+}
+
+impl JitProgram {
+    pub fn compile(compiler: &ImCompiler) -> Result<Self, DecodeError> {
+        let program = Node::decode(&compiler.future_tape)?;
+        let float_globals = float_globals(&program, compiler.globals.len());
+
+        let mut jit = Jit {
+            code: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+            float_globals: &float_globals,
+        };
+        jit.function(&program);
+        let code = jit.finish();
+
+        let code_len = code.len().max(1);
+        let mem = unsafe {
+            let mem = mmap(
+                ptr::null_mut(),
+                code_len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+
+            if mem as isize == -1 {
+                panic!("Couldn't map memory for the JIT");
+            }
+
+            ptr::copy_nonoverlapping(code.as_ptr(), mem as *mut u8, code.len());
+
+            if mprotect(mem, code_len, PROT_READ | PROT_EXEC) != 0 {
+                panic!("Couldn't make JIT memory executable");
+            }
+
+            mem
+        };
+
+        Ok(Self {
+            code: mem,
+            code_len,
+            tape: compiler.future_tape.clone(),
+            globals: compiler.globals.len(),
+            float_globals: float_globals
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, is_float)| is_float.then_some(idx))
+                .collect(),
+        })
+    }
+
+    /// A context running on the program's own tape.
+    pub fn context(&self) -> CallContext {
+        CallContext::new(self.tape.as_ptr(), self.tape.len(), self.globals)
+    }
+
+    /// Runs the program like [`CallContext::run`]. A program that halted
+    /// can't carry on, it's [abandoned](CallContext::abandon) and the
+    /// reason it halted is returned.
+    pub fn run(&self, ctx: &mut CallContext) -> Outcome {
+        let value = self.execute(ctx);
+
+        match ctx.abandon() {
+            Some(halt) => halt,
+            None => Outcome::Finished(value),
+        }
+    }
+
+    /// Runs the program, `ctx` has to come from [`Self::context`].
+    pub fn execute(&self, ctx: &mut CallContext) -> Value {
+        let mut slots = vec![0.0; self.globals];
+        let mut frame = Frame {
+            ctx,
+            slots: slots.as_mut_ptr(),
+            float_globals: &self.float_globals,
+            result: Value::Nil,
+        };
+
+        unsafe {
+            load_slots(&mut frame);
+
+            let entry: extern "C" fn(*mut Frame) = std::mem::transmute(self.code);
+            entry(&mut frame);
+
+            store_slots(&mut frame);
+        }
+
+        frame.result
+    }
+}
+
+unsafe fn store_slots(frame: &mut Frame) {
+    let ctx = &mut *frame.ctx;
+
+    for &idx in &*frame.float_globals {
+        ctx.globals[idx] = Value::Float(*frame.slots.add(idx));
+    }
+}
+
+unsafe fn load_slots(frame: &mut Frame) {
+    let ctx = &mut *frame.ctx;
+
+    for &idx in &*frame.float_globals {
+        *frame.slots.add(idx) = ctx.globals[idx]
+            .as_float()
+            .expect("Float global holds a non float value");
+    }
+}
+
+/// Runs the statement at `offset` on the tape, returns 1 if it returned
+/// from the enclosing block or halted. Like the statements of a block on
+/// the tape it checks for interrupts first.
+extern "C" fn fallback(frame: &mut Frame, offset: u64) -> u64 {
+    unsafe {
+        let ctx = &mut *frame.ctx;
+
+        if ctx.interrupted() {
+            frame.result = Value::Nil;
+            return 1;
+        }
+
+        store_slots(frame);
+        ctx.tape.move_to(offset as usize);
+
+        let returned = match Hint::from_cell(ctx.tape.read()) {
+            Some(Hint::Return) => {
+                ctx.tape.skip(1);
+                Some(ctx.tape.get_next_func::<Value>().call(ctx))
+            }
+            Some(Hint::While) => {
+                ctx.tape.skip(1);
+                ctx.tape.get_next_func::<Option<Value>>().call(ctx)
+            }
+            _ => {
+                ctx.tape.get_next_func::<Value>().call(ctx);
+                ctx.take_return()
+            }
+        };
+
+        load_slots(frame);
+
+        if ctx.halted() {
+            frame.result = Value::Nil;
+            return 1;
+        }
+
+        match returned {
+            Some(value) => {
+                frame.result = value;
+                1
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Evaluates the program at `offset` on the tape when it isn't a block.
+extern "C" fn evaluate(frame: &mut Frame, offset: u64) {
+    unsafe {
+        store_slots(frame);
+
+        let ctx = &mut *frame.ctx;
+        ctx.tape.move_to(offset as usize);
+
+        let value = ctx.tape.get_next_func::<Value>().call(ctx);
+
+        frame.result = if ctx.halted() {
+            Value::Nil
+        } else {
+            ctx.take_return().unwrap_or(value)
+        };
+
+        load_slots(frame);
+    }
+}
+
+extern "C" fn return_float(frame: &mut Frame, value: f64) {
+    frame.result = Value::Float(value);
+}
+
+extern "C" fn rem(lhs: f64, rhs: f64) -> f64 {
+    lhs % rhs
+}
+
+/// Finds the globals that only ever hold floats: the ones only assigned
+/// float expressions and never bound to an exception (everything starts out
+/// as `Float(0.0)`).
+fn float_globals(program: &Node, amount: usize) -> Vec<bool> {
+    fn check(node: &Node, floats: &mut Vec<bool>, changed: &mut bool) {
+        match &node.kind {
+            NodeKind::Assign(idx, value) if floats[*idx as usize] && !is_float(value, floats) => {
+                floats[*idx as usize] = false;
+                *changed = true;
+            }
+            // Any value can be thrown.
+            NodeKind::Try { binding, .. } if floats[*binding as usize] => {
+                floats[*binding as usize] = false;
+                *changed = true;
+            }
+            _ => {}
+        }
+
+        for child in node.children() {
+            check(child, floats, changed);
+        }
+    }
+
+    let mut floats = vec![true; amount];
+
+    loop {
+        let mut changed = false;
+        check(program, &mut floats, &mut changed);
+
+        if !changed {
+            return floats;
+        }
+    }
+}
+
+fn is_arithmetic(op: OpCode) -> bool {
+    matches!(
+        op.operator(),
+        Some(Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Rem)
+    )
+}
+
+/// Whether `node` evaluates to a float the JIT can compute natively.
+fn is_float(node: &Node, floats: &[bool]) -> bool {
+    match &node.kind {
+        NodeKind::Float(_) | NodeKind::RawFloat(_) => true,
+        NodeKind::Var(idx) | NodeKind::RawVar(idx) => floats[*idx as usize],
+        NodeKind::BinaryOp(op, lhs, rhs) => {
+            is_arithmetic(*op) && is_float(lhs, floats) && is_float(rhs, floats)
+        }
+        _ => false,
+    }
+}
+
+// Registers used by the templates: rbx holds the frame, r12 the slots.
+const MOVSD_LOAD: [u8; 3] = [0xf2, 0x0f, 0x10];
+const MOVSD_STORE: [u8; 3] = [0xf2, 0x0f, 0x11];
+
+#[derive(Clone, Copy)]
+struct Label(usize);
+
+struct Jit<'a> {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<(usize, Label)>,
+    float_globals: &'a [bool],
+}
+
+impl<'a> Jit<'a> {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    fn jmp(&mut self, label: Label) {
+        self.emit(&[0xe9]);
+        self.rel32(label);
+    }
+
+    fn jcc(&mut self, condition: u8, label: Label) {
+        self.emit(&[0x0f, condition]);
+        self.rel32(label);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (at, label) in std::mem::take(&mut self.fixups) {
+            let target = self.labels[label.0].expect("Unbound label") as i32;
+            let rel = target - (at as i32 + 4);
+            self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        }
+
+        self.code
+    }
+
+    fn call(&mut self, func: usize) {
+        // mov rax, func; call rax
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&(func as u64).to_le_bytes());
+        self.emit(&[0xff, 0xd0]);
+    }
+
+    /// movsd xmm{reg}, [r12 + idx * 8] (or the other way around)
+    fn slot(&mut self, opcode: [u8; 3], reg: u8, idx: u64) {
+        self.emit(&[
+            opcode[0],
+            0x41,
+            opcode[1],
+            opcode[2],
+            0x84 | (reg << 3),
+            0x24,
+        ]);
+        self.emit(&((idx * 8) as u32).to_le_bytes());
+    }
+
+    /// Loads a float constant in xmm{reg}.
+    fn constant(&mut self, reg: u8, value: f64) {
+        // mov rax, bits; movq xmm{reg}, rax
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&value.to_bits().to_le_bytes());
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc0 | (reg << 3)]);
+    }
+
+    fn function(&mut self, program: &Node) {
+        let exit = self.label();
+
+        // push rbx; push r12; push r13 (keeps the stack aligned for calls)
+        self.emit(&[0x53, 0x41, 0x54, 0x41, 0x55]);
+        // mov rbx, rdi; mov r12, [rbx + slots]
+        self.emit(&[0x48, 0x89, 0xfb]);
+        self.emit(&[0x4c, 0x8b, 0x63, FRAME_SLOTS]);
+
+        match &program.kind {
+            NodeKind::Block { body, .. } => {
+                for statement in body {
+                    self.statement(statement, exit);
+                }
+            }
+            _ => {
+                // mov rdi, rbx; mov rsi, offset
+                self.emit(&[0x48, 0x89, 0xdf, 0x48, 0xbe]);
+                self.emit(&(program.offset as u64).to_le_bytes());
+                self.call(evaluate as *const () as usize);
+            }
+        }
+
+        self.bind(exit);
+        // pop r13; pop r12; pop rbx; ret
+        self.emit(&[0x41, 0x5d, 0x41, 0x5c, 0x5b, 0xc3]);
+    }
+
+    fn fallback(&mut self, node: &Node, exit: Label) {
+        // mov rdi, rbx; mov rsi, offset
+        self.emit(&[0x48, 0x89, 0xdf, 0x48, 0xbe]);
+        self.emit(&(node.offset as u64).to_le_bytes());
+        self.call(fallback as *const () as usize);
+        // test rax, rax; jnz exit
+        self.emit(&[0x48, 0x85, 0xc0]);
+        self.jcc(0x85, exit);
+    }
+
+    /// Compiles a statement, `exit` is jumped to when it returns.
+    fn statement(&mut self, node: &Node, exit: Label) {
+        let floats = self.float_globals;
+
+        match &node.kind {
+            NodeKind::Assign(idx, value) if floats[*idx as usize] && is_float(value, floats) => {
+                self.float(value);
+                self.slot(MOVSD_STORE, 0, *idx);
+            }
+
+            NodeKind::Return(value) if is_float(value, floats) => {
+                self.float(value);
+                // mov rdi, rbx
+                self.emit(&[0x48, 0x89, 0xdf]);
+                self.call(return_float as *const () as usize);
+                self.jmp(exit);
+            }
+
+            NodeKind::Block {
+                checked: false,
+                body,
+            } => {
+                for statement in body {
+                    self.statement(statement, exit);
+                }
+            }
+
+            NodeKind::While(cond, body) if self.is_condition(cond) => {
+                let (start, end) = (self.label(), self.label());
+
+                self.bind(start);
+                self.condition(cond, end);
+                self.statement(body, exit);
+                self.jmp(start);
+                self.bind(end);
+            }
+
+            _ => self.fallback(node, exit),
+        }
+    }
+
+    fn is_condition(&self, node: &Node) -> bool {
+        let floats = self.float_globals;
+
+        match &node.kind {
+            NodeKind::Boolean(_) => true,
+            NodeKind::BinaryOp(op, lhs, rhs) if !is_arithmetic(*op) => {
+                is_float(lhs, floats) && is_float(rhs, floats)
+            }
+            _ => is_float(node, floats),
+        }
+    }
+
+    /// Jumps to `if_false` when `node` isn't truthy.
+    fn condition(&mut self, node: &Node, if_false: Label) {
+        const JB: u8 = 0x82;
+        const JE: u8 = 0x84;
+        const JNE: u8 = 0x85;
+        const JBE: u8 = 0x86;
+        const JP: u8 = 0x8a;
+
+        let (operator, lhs, rhs) = match &node.kind {
+            NodeKind::Boolean(true) => return,
+            NodeKind::Boolean(false) => return self.jmp(if_false),
+            NodeKind::BinaryOp(op, lhs, rhs) if !is_arithmetic(*op) => {
+                (op.operator().unwrap(), &**lhs, &**rhs)
+            }
+            // Floats are only truthy when equal to 1.0
+            _ => {
+                self.float(node);
+                self.constant(1, 1.0);
+                // ucomisd xmm0, xmm1
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc1]);
+                self.jcc(JNE, if_false);
+                self.jcc(JP, if_false);
+                return;
+            }
+        };
+
+        self.operands(lhs, rhs);
+
+        // Unordered comparisons set ZF, PF and CF, so NaNs go to `if_false`
+        match operator {
+            Operator::Gt | Operator::Gte => {
+                // ucomisd xmm0, xmm1
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc1]);
+                self.jcc(if operator == Operator::Gt { JBE } else { JB }, if_false);
+            }
+            Operator::Lt | Operator::Lte => {
+                // ucomisd xmm1, xmm0
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc8]);
+                self.jcc(if operator == Operator::Lt { JBE } else { JB }, if_false);
+            }
+            Operator::Eq => {
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc1]);
+                self.jcc(JNE, if_false);
+                self.jcc(JP, if_false);
+            }
+            Operator::Neq => {
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc1]);
+                // jp over the je
+                self.emit(&[0x7a, 0x06]);
+                self.jcc(JE, if_false);
+            }
+            _ => unreachable!("Not a comparison: {operator:?}"),
+        }
+    }
+
+    /// Evaluates `lhs` in xmm0 and `rhs` in xmm1.
+    fn operands(&mut self, lhs: &Node, rhs: &Node) {
+        match &rhs.kind {
+            NodeKind::Float(x) | NodeKind::RawFloat(x) => {
+                self.float(lhs);
+                self.constant(1, *x);
+            }
+            NodeKind::Var(idx) | NodeKind::RawVar(idx) => {
+                self.float(lhs);
+                self.slot(MOVSD_LOAD, 1, *idx);
+            }
+            _ => {
+                self.float(lhs);
+                // sub rsp, 16; movsd [rsp], xmm0
+                self.emit(&[0x48, 0x83, 0xec, 0x10, 0xf2, 0x0f, 0x11, 0x04, 0x24]);
+                self.float(rhs);
+                // movapd xmm1, xmm0; movsd xmm0, [rsp]; add rsp, 16
+                self.emit(&[0x66, 0x0f, 0x28, 0xc8, 0xf2, 0x0f, 0x10, 0x04, 0x24]);
+                self.emit(&[0x48, 0x83, 0xc4, 0x10]);
+            }
+        }
+    }
+
+    /// Evaluates a node for which [`is_float`] holds in xmm0.
+    fn float(&mut self, node: &Node) {
+        match &node.kind {
+            NodeKind::Float(x) | NodeKind::RawFloat(x) => self.constant(0, *x),
+            NodeKind::Var(idx) | NodeKind::RawVar(idx) => self.slot(MOVSD_LOAD, 0, *idx),
+            NodeKind::BinaryOp(op, lhs, rhs) => {
+                self.operands(lhs, rhs);
+
+                match op.operator().unwrap() {
+                    Operator::Add => self.emit(&[0xf2, 0x0f, 0x58, 0xc1]),
+                    Operator::Mul => self.emit(&[0xf2, 0x0f, 0x59, 0xc1]),
+                    Operator::Sub => self.emit(&[0xf2, 0x0f, 0x5c, 0xc1]),
+                    Operator::Div => self.emit(&[0xf2, 0x0f, 0x5e, 0xc1]),
+                    Operator::Rem => self.call(rem as *const () as usize),
+                    operator => unreachable!("Not an arithmetic operator: {operator:?}"),
+                }
+            }
+            kind => unreachable!("Not a float node: {kind:?}"),
+        }
+    }
+}
+
+#[test]
+pub fn jit() {
+    use crate::expr::*;
+
+    let global = |name: &str| Binding::Global(name.into());
+
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(1000.0)),
+        global("flag").assign(Expr::Boolean(true)),
+        Expr::While(
+            global("x").var().op(Operator::Gt, Expr::Float(0.0)).into(),
+            Expr::Block(vec![
+                global("y").assign(global("y").var().op(
+                    Operator::Add,
+                    global("x").var().op(Operator::Rem, Expr::Float(7.0)),
+                )),
+                global("x").assign(global("x").var().op(Operator::Sub, Expr::Float(1.0))),
+                // Runs on the tape
+                global("flag").assign(global("x").var().op(Operator::Neq, Expr::Float(f64::NAN))),
+            ])
+            .into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program.clone());
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut jit_ctx = jit.context();
+    let jit_result = jit.execute(&mut jit_ctx);
+
+    let mut ctx = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    let result = ctx.execute();
+
+    assert_eq!(jit_result, result);
+    assert_eq!(jit_ctx.globals, ctx.globals);
+    assert_eq!(
+        ctx.globals,
+        vec![
+            Value::Float(0.0),
+            Value::Boolean(true),
+            Value::Float(3003.0)
+        ]
+    );
+
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(10.0)),
+        Expr::While(
+            global("x").var().op(Operator::Gt, Expr::Float(2.0)).into(),
+            Expr::Block(vec![
+                global("x").assign(global("x").var().op(Operator::Sub, Expr::Float(1.0)))
+            ])
+            .into(),
+        ),
+        Expr::While(
+            Expr::Boolean(true).into(),
+            Expr::Return(global("x").var().op(Operator::Mul, Expr::Float(3.0)).into()).into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut ctx = jit.context();
+
+    assert_eq!(jit.execute(&mut ctx), Value::Float(6.0));
+    assert_eq!(ctx.globals, vec![Value::Float(2.0)]);
+    // Returns from a conditional in the body of the loop.
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(0.0)),
+        Expr::While(
+            Expr::Boolean(true).into(),
+            Expr::Block(vec![
+                global("x").assign(global("x").var().op(Operator::Add, Expr::Float(1.0))),
+                Expr::Conditional(
+                    (
+                        global("x").var().op(Operator::Gt, Expr::Float(4.0)),
+                        Expr::Return(global("x").var().op(Operator::Mul, Expr::Float(2.0)).into()),
+                    )
+                        .into(),
+                    vec![],
+                    None,
+                ),
+            ])
+            .into(),
+        ),
+        Expr::Return(Expr::Float(-1.0).into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut ctx = jit.context();
+
+    assert_eq!(jit.execute(&mut ctx), Value::Float(10.0));
+    assert_eq!(ctx.globals, vec![Value::Float(5.0)]);
+
+    // The catch binding holds whatever was thrown.
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(1.0)),
+        global("e").assign(Expr::Float(0.0)),
+        Expr::Try {
+            body: Expr::Throw(Expr::Boolean(true).into()).into(),
+            catch_binding: global("e"),
+            handler: global("x")
+                .assign(global("x").var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+            finally: None,
+        },
+        Expr::Return(global("x").var().into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut ctx = jit.context();
+
+    assert_eq!(jit.run(&mut ctx), Outcome::Finished(Value::Float(2.0)));
+    assert_eq!(ctx.globals, vec![Value::Float(2.0), Value::Boolean(true)]);
+
+    // Exceptions nothing catches stop the program.
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(1.0)),
+        Expr::Throw(global("x").var().into()),
+        global("x").assign(Expr::Float(2.0)),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut ctx = jit.context();
+
+    assert_eq!(jit.run(&mut ctx), Outcome::Threw(Value::Float(1.0)));
+    assert_eq!(ctx.globals, vec![Value::Float(1.0)]);
+
+    // Programs that aren't blocks evaluate to their value.
+    let program = Expr::Conditional(
+        (Expr::Boolean(false), Expr::Float(1.0)).into(),
+        vec![],
+        Some(Expr::Float(2.0).op(Operator::Mul, Expr::Float(3.0)).into()),
+    );
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut jit_ctx = jit.context();
+
+    let mut ctx = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    assert_eq!(ctx.execute(), Value::Float(6.0));
+    assert_eq!(jit.execute(&mut jit_ctx), Value::Float(6.0));
+
+    // Halting on the tape stops the program, which runs from the start
+    // next time.
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(0.0)),
+        global("flag").assign(Expr::Boolean(true)),
+        Expr::While(
+            global("x")
+                .var()
+                .op(Operator::Lt, Expr::Float(100.0))
+                .into(),
+            Expr::Block(vec![
+                global("x").assign(global("x").var().op(Operator::Add, Expr::Float(1.0))),
+                // Runs on the tape
+                global("flag").assign(global("x").var().op(Operator::Neq, Expr::Float(f64::NAN))),
+            ])
+            .into(),
+        ),
+        Expr::Return(global("x").var().into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let jit = JitProgram::compile(&compiler).unwrap();
+    let mut ctx = jit.context();
+
+    ctx.set_fuel(Some(50));
+    assert_eq!(jit.run(&mut ctx), Outcome::OutOfFuel);
+    assert!(!ctx.is_suspended());
+
+    ctx.set_fuel(None);
+    ctx.interrupt_handle().interrupt();
+    assert_eq!(jit.run(&mut ctx), Outcome::Interrupted);
+    assert!(!ctx.is_suspended());
+
+    assert_eq!(jit.run(&mut ctx), Outcome::Finished(Value::Float(100.0)));
+}
@@ -0,0 +1,95 @@
+use crate::*;
+
+impl ImCompiler {
+    /// Encodes the compiled tape with the stable [`OpCode::id`] of every
+    /// operation instead of its address, so it can be stored and loaded by
+    /// another build of the interpreter.
+    ///
+    /// Every other cell (immediates, global indices, hints and jump
+    /// targets) is copied as is, offsets are already relative to the start
+    /// of the tape.
+    pub fn encode(&self) -> Result<Vec<u64>, DecodeError> {
+        let program = Node::decode(&self.future_tape)?;
+
+        let mut encoded = Vec::with_capacity(self.future_tape.len());
+        program.emit_with(&mut encoded, OpCode::id);
+
+        Ok(encoded)
+    }
+
+    /// Links a tape produced by [`ImCompiler::encode`], resolving every
+    /// operation id to the address of the operation in this build.
+    pub fn link(globals: Vec<String>, encoded: &[u64]) -> Result<ImCompiler, DecodeError> {
+        let program = Node::decode_with(encoded, OpCode::from_id)?;
+
+        let mut compiler = ImCompiler::new();
+        compiler.globals = globals;
+        program.emit(&mut compiler.future_tape);
+
+        Ok(compiler)
+    }
+}
+
+#[test]
+pub fn linking() {
+    use crate::expr::*;
+
+    for (i, op) in OpCode::ALL.into_iter().enumerate() {
+        assert_eq!(OpCode::from_id(op.id()), Some(op));
+        assert_eq!(
+            Instr::decode(op.id(), OpCode::from_id, false),
+            Ok(Instr::Op(op))
+        );
+        assert_eq!(
+            Instr::decode(op.address(), OpCode::from_address, false),
+            Ok(Instr::Op(op))
+        );
+        assert!(OpCode::ALL[..i].iter().all(|other| other.id() != op.id()));
+    }
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Binding::Global("y".into()).assign(x().var().op(Operator::Eq, Expr::Float(10.0))),
+        Expr::Return(x().var().into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let encoded = compiler.encode().unwrap();
+
+    assert_eq!(encoded.len(), compiler.future_tape.len());
+    assert!(encoded
+        .iter()
+        .all(|cell| OpCode::from_address(*cell).is_none()));
+
+    let linked = ImCompiler::link(compiler.globals.clone(), &encoded).unwrap();
+    assert_eq!(linked.future_tape, compiler.future_tape);
+
+    let mut context = CallContext::new(
+        linked.future_tape.as_ptr(),
+        linked.future_tape.len(),
+        linked.globals.len(),
+    );
+    context.execute();
+
+    assert_eq!(
+        context.globals,
+        vec![Value::Float(10.0), Value::Boolean(true)]
+    );
+
+    assert!(matches!(
+        ImCompiler::link(vec![], &[999]),
+        Err(DecodeError::UnknownOp {
+            offset: 0,
+            cell: 999
+        })
+    ));
+}
@@ -1,5 +1,8 @@
 pub use std::mem::transmute;
 
+pub mod value;
+pub use value::*;
+
 pub mod imsta;
 pub use imsta::*;
 
@@ -7,4 +10,46 @@ pub mod dissassembler;
 pub use dissassembler::*;
 
 pub mod implementations;
-pub use implementations::*;
\ No newline at end of file
+pub use implementations::*;
+
+pub mod opcodes;
+pub use opcodes::*;
+
+pub mod tree;
+pub use tree::*;
+
+pub mod peephole;
+
+pub mod linking;
+
+pub mod bytecode;
+pub use bytecode::*;
+
+pub mod verifier;
+pub use verifier::*;
+
+pub mod decompiler;
+pub use decompiler::*;
+
+pub mod assembler;
+pub use assembler::*;
+
+#[cfg(feature = "debugger")]
+pub mod debugger;
+#[cfg(feature = "debugger")]
+pub use debugger::*;
+
+#[cfg(feature = "tracing")]
+pub mod tracer;
+#[cfg(feature = "tracing")]
+pub use tracer::*;
+
+#[cfg(feature = "profiler")]
+pub mod profiler;
+#[cfg(feature = "profiler")]
+pub use profiler::*;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod jit;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use jit::*;
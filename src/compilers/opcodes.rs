@@ -0,0 +1,349 @@
+use crate::expr::Operator;
+use crate::*;
+
+/// A cell, or group of cells, following an operation on the tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// The bits of an `f64` immediate.
+    Float,
+    /// Index of a global.
+    Global,
+    /// Absolute offset to continue at.
+    Jump,
+    /// An instruction evaluating to a `Value`.
+    Value,
+    /// An instruction evaluating to a raw `f64`.
+    Raw,
+    /// A `Value` instruction run as a statement, possibly hinted.
+    Statement,
+    /// `Statement`s up to the target of the preceding `Jump`.
+    Body,
+    /// Number of times the following `Branch` repeats.
+    Count,
+    /// A `Jump` past the branch, then its `Value` condition and `Statement`
+    /// body.
+    Branch,
+}
+
+/// Every operation that can appear on a tape.
+///
+/// The discriminants are the operations' stable ids, used to encode tapes
+/// independently of where the operations live in memory. Never renumber
+/// an operation, new ones get the next free id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum OpCode {
+    True = 1,
+    False = 2,
+    Float = 3,
+    Var = 4,
+    Assign = 5,
+    Block = 6,
+    BlockChecked = 7,
+    WhileLoop = 8,
+    Conditional = 9,
+    Add = 10,
+    Sub = 11,
+    Mul = 12,
+    Div = 13,
+    Rem = 14,
+    Eq = 15,
+    Neq = 16,
+    Gt = 17,
+    Gte = 18,
+    Lt = 19,
+    Lte = 20,
+    RawFloat = 21,
+    RawVar = 22,
+    RawAdd = 23,
+    RawSub = 24,
+    RawMul = 25,
+    RawDiv = 26,
+    RawRem = 27,
+    F64Add = 28,
+    F64Sub = 29,
+    F64Mul = 30,
+    F64Div = 31,
+    F64Rem = 32,
+    F64Eq = 33,
+    F64Neq = 34,
+    F64Gt = 35,
+    F64Gte = 36,
+    F64Lt = 37,
+    F64Lte = 38,
+    Yield = 39,
+    Coroutine = 40,
+    Resume = 41,
+    Done = 42,
+    Throw = 43,
+    Try = 44,
+}
+
+impl OpCode {
+    pub const ALL: [OpCode; 44] = [
+        OpCode::True,
+        OpCode::False,
+        OpCode::Float,
+        OpCode::Var,
+        OpCode::Assign,
+        OpCode::Block,
+        OpCode::BlockChecked,
+        OpCode::WhileLoop,
+        OpCode::Conditional,
+        OpCode::Add,
+        OpCode::Sub,
+        OpCode::Mul,
+        OpCode::Div,
+        OpCode::Rem,
+        OpCode::Eq,
+        OpCode::Neq,
+        OpCode::Gt,
+        OpCode::Gte,
+        OpCode::Lt,
+        OpCode::Lte,
+        OpCode::RawFloat,
+        OpCode::RawVar,
+        OpCode::RawAdd,
+        OpCode::RawSub,
+        OpCode::RawMul,
+        OpCode::RawDiv,
+        OpCode::RawRem,
+        OpCode::F64Add,
+        OpCode::F64Sub,
+        OpCode::F64Mul,
+        OpCode::F64Div,
+        OpCode::F64Rem,
+        OpCode::F64Eq,
+        OpCode::F64Neq,
+        OpCode::F64Gt,
+        OpCode::F64Gte,
+        OpCode::F64Lt,
+        OpCode::F64Lte,
+        OpCode::Yield,
+        OpCode::Coroutine,
+        OpCode::Resume,
+        OpCode::Done,
+        OpCode::Throw,
+        OpCode::Try,
+    ];
+
+    /// The tape cell holding this operation.
+    pub fn address(self) -> u64 {
+        let address = match self {
+            OpCode::True => literals::tr as *const () as usize,
+            OpCode::False => literals::fl as *const () as usize,
+            OpCode::Float => literals::float as *const () as usize,
+            OpCode::Var => operations::var as *const () as usize,
+            OpCode::Assign => operations::assign as *const () as usize,
+            OpCode::Block => flow::block as *const () as usize,
+            OpCode::BlockChecked => flow::block_checked as *const () as usize,
+            OpCode::WhileLoop => flow::while_loop as *const () as usize,
+            OpCode::Conditional => flow::conditional as *const () as usize,
+            OpCode::Add => operations::native_op_add as *const () as usize,
+            OpCode::Sub => operations::native_op_sub as *const () as usize,
+            OpCode::Mul => operations::native_op_mul as *const () as usize,
+            OpCode::Div => operations::native_op_div as *const () as usize,
+            OpCode::Rem => operations::native_op_rem as *const () as usize,
+            OpCode::Eq => operations::native_op_eq as *const () as usize,
+            OpCode::Neq => operations::native_op_neq as *const () as usize,
+            OpCode::Gt => operations::native_op_gt as *const () as usize,
+            OpCode::Gte => operations::native_op_gte as *const () as usize,
+            OpCode::Lt => operations::native_op_lt as *const () as usize,
+            OpCode::Lte => operations::native_op_lte as *const () as usize,
+            OpCode::RawFloat => specialized::raw_float as *const () as usize,
+            OpCode::RawVar => specialized::raw_var as *const () as usize,
+            OpCode::RawAdd => specialized::raw_add as *const () as usize,
+            OpCode::RawSub => specialized::raw_sub as *const () as usize,
+            OpCode::RawMul => specialized::raw_mul as *const () as usize,
+            OpCode::RawDiv => specialized::raw_div as *const () as usize,
+            OpCode::RawRem => specialized::raw_rem as *const () as usize,
+            OpCode::F64Add => specialized::f64_add as *const () as usize,
+            OpCode::F64Sub => specialized::f64_sub as *const () as usize,
+            OpCode::F64Mul => specialized::f64_mul as *const () as usize,
+            OpCode::F64Div => specialized::f64_div as *const () as usize,
+            OpCode::F64Rem => specialized::f64_rem as *const () as usize,
+            OpCode::F64Eq => specialized::f64_eq as *const () as usize,
+            OpCode::F64Neq => specialized::f64_neq as *const () as usize,
+            OpCode::F64Gt => specialized::f64_gt as *const () as usize,
+            OpCode::F64Gte => specialized::f64_gte as *const () as usize,
+            OpCode::F64Lt => specialized::f64_lt as *const () as usize,
+            OpCode::F64Lte => specialized::f64_lte as *const () as usize,
+            OpCode::Yield => flow::yield_value as *const () as usize,
+            OpCode::Coroutine => coroutines::create as *const () as usize,
+            OpCode::Resume => coroutines::resume as *const () as usize,
+            OpCode::Done => coroutines::done as *const () as usize,
+            OpCode::Throw => flow::throw as *const () as usize,
+            OpCode::Try => flow::try_catch as *const () as usize,
+        };
+
+        address as u64
+    }
+
+    pub fn from_address(cell: u64) -> Option<OpCode> {
+        OpCode::ALL.into_iter().find(|op| op.address() == cell)
+    }
+
+    /// The stable id of the operation.
+    pub fn id(self) -> u64 {
+        self as u64
+    }
+
+    pub fn from_id(id: u64) -> Option<OpCode> {
+        OpCode::ALL.into_iter().find(|op| op.id() == id)
+    }
+
+    /// The layout of the cells following the operation.
+    pub fn operands(self) -> &'static [Operand] {
+        use Operand::*;
+
+        match self {
+            OpCode::True | OpCode::False => &[],
+            OpCode::Float | OpCode::RawFloat => &[Float],
+            OpCode::Var | OpCode::RawVar => &[Global],
+            OpCode::Assign => &[Global, Value],
+            OpCode::Yield | OpCode::Done | OpCode::Throw => &[Value],
+            OpCode::Coroutine => &[Jump, Value],
+            OpCode::Resume => &[Value, Value],
+            OpCode::Try => &[Jump, Jump, Global, Value, Value, Value],
+            OpCode::Block | OpCode::BlockChecked => &[Jump, Body],
+            OpCode::WhileLoop => &[Jump, Value, Statement],
+            OpCode::Conditional => &[Count, Jump, Branch, Statement],
+            // Every binary operation but the generic ones works on floats.
+            op => match op.operator() {
+                Some(operator) if OpCode::from_operator(operator) != op => &[Raw, Raw],
+                _ => &[Value, Value],
+            },
+        }
+    }
+
+    /// Whether the operation evaluates to a raw `f64` instead of a `Value`.
+    pub fn is_raw(self) -> bool {
+        matches!(
+            self,
+            OpCode::RawFloat
+                | OpCode::RawVar
+                | OpCode::RawAdd
+                | OpCode::RawSub
+                | OpCode::RawMul
+                | OpCode::RawDiv
+                | OpCode::RawRem
+        )
+    }
+
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        OpCode::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::True => "true",
+            OpCode::False => "false",
+            OpCode::Float => "float",
+            OpCode::Var => "var",
+            OpCode::Assign => "assign",
+            OpCode::Block => "block",
+            OpCode::BlockChecked => "block_checked",
+            OpCode::WhileLoop => "while_loop",
+            OpCode::Conditional => "conditional",
+            OpCode::Add => "add",
+            OpCode::Sub => "sub",
+            OpCode::Mul => "mul",
+            OpCode::Div => "div",
+            OpCode::Rem => "rem",
+            OpCode::Eq => "eq",
+            OpCode::Neq => "neq",
+            OpCode::Gt => "gt",
+            OpCode::Gte => "gte",
+            OpCode::Lt => "lt",
+            OpCode::Lte => "lte",
+            OpCode::RawFloat => "raw_float",
+            OpCode::RawVar => "raw_var",
+            OpCode::RawAdd => "raw_add",
+            OpCode::RawSub => "raw_sub",
+            OpCode::RawMul => "raw_mul",
+            OpCode::RawDiv => "raw_div",
+            OpCode::RawRem => "raw_rem",
+            OpCode::F64Add => "f64_add",
+            OpCode::F64Sub => "f64_sub",
+            OpCode::F64Mul => "f64_mul",
+            OpCode::F64Div => "f64_div",
+            OpCode::F64Rem => "f64_rem",
+            OpCode::F64Eq => "f64_eq",
+            OpCode::F64Neq => "f64_neq",
+            OpCode::F64Gt => "f64_gt",
+            OpCode::F64Gte => "f64_gte",
+            OpCode::F64Lt => "f64_lt",
+            OpCode::F64Lte => "f64_lte",
+            OpCode::Yield => "yield",
+            OpCode::Coroutine => "coroutine",
+            OpCode::Resume => "resume",
+            OpCode::Done => "done",
+            OpCode::Throw => "throw",
+            OpCode::Try => "try",
+        }
+    }
+
+    /// The float-only variant of a binary operation, see
+    /// [`specialized`](crate::specialized).
+    pub fn specialized(operator: Operator) -> OpCode {
+        match operator {
+            Operator::Add => OpCode::F64Add,
+            Operator::Sub => OpCode::F64Sub,
+            Operator::Mul => OpCode::F64Mul,
+            Operator::Div => OpCode::F64Div,
+            Operator::Rem => OpCode::F64Rem,
+            Operator::Eq => OpCode::F64Eq,
+            Operator::Neq => OpCode::F64Neq,
+            Operator::Gt => OpCode::F64Gt,
+            Operator::Gte => OpCode::F64Gte,
+            Operator::Lt => OpCode::F64Lt,
+            Operator::Lte => OpCode::F64Lte,
+        }
+    }
+
+    /// The `f64` returning variant of an arithmetic operation.
+    pub fn raw(operator: Operator) -> Option<OpCode> {
+        Some(match operator {
+            Operator::Add => OpCode::RawAdd,
+            Operator::Sub => OpCode::RawSub,
+            Operator::Mul => OpCode::RawMul,
+            Operator::Div => OpCode::RawDiv,
+            Operator::Rem => OpCode::RawRem,
+            _ => return None,
+        })
+    }
+
+    pub fn from_operator(operator: Operator) -> OpCode {
+        match operator {
+            Operator::Add => OpCode::Add,
+            Operator::Sub => OpCode::Sub,
+            Operator::Mul => OpCode::Mul,
+            Operator::Div => OpCode::Div,
+            Operator::Rem => OpCode::Rem,
+            Operator::Eq => OpCode::Eq,
+            Operator::Neq => OpCode::Neq,
+            Operator::Gt => OpCode::Gt,
+            Operator::Gte => OpCode::Gte,
+            Operator::Lt => OpCode::Lt,
+            Operator::Lte => OpCode::Lte,
+        }
+    }
+
+    /// The operator implemented by a binary operation.
+    pub fn operator(self) -> Option<Operator> {
+        Some(match self {
+            OpCode::Add | OpCode::F64Add | OpCode::RawAdd => Operator::Add,
+            OpCode::Sub | OpCode::F64Sub | OpCode::RawSub => Operator::Sub,
+            OpCode::Mul | OpCode::F64Mul | OpCode::RawMul => Operator::Mul,
+            OpCode::Div | OpCode::F64Div | OpCode::RawDiv => Operator::Div,
+            OpCode::Rem | OpCode::F64Rem | OpCode::RawRem => Operator::Rem,
+            OpCode::Eq | OpCode::F64Eq => Operator::Eq,
+            OpCode::Neq | OpCode::F64Neq => Operator::Neq,
+            OpCode::Gt | OpCode::F64Gt => Operator::Gt,
+            OpCode::Gte | OpCode::F64Gte => Operator::Gte,
+            OpCode::Lt | OpCode::F64Lt => Operator::Lt,
+            OpCode::Lte | OpCode::F64Lte => Operator::Lte,
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use crate::*;
+
+impl ImCompiler {
+    /// Rewrites local patterns on the compiled tape:
+    ///
+    /// - blocks used as statements are spliced into the enclosing block,
+    ///   unless they're checked and return from themselves
+    /// - `block_checked` is downgraded to `block` when none of its
+    ///   statements can return
+    /// - a conditional whose `else` body is another conditional takes over
+    ///   its branches, so the inner end jump is threaded into the outer one
+    ///
+    /// The tape is re-emitted afterwards, which relocates every offset
+    /// stored in `block`, `while_loop`, `conditional`, `coroutine` and `try`
+    /// headers.
+    pub fn peephole(&mut self) -> Result<(), DecodeError> {
+        let mut program = Node::decode(&self.future_tape)?;
+        rewrite(&mut program);
+
+        self.future_tape.clear();
+        program.emit(&mut self.future_tape);
+
+        Ok(())
+    }
+}
+
+fn rewrite(node: &mut Node) {
+    match &mut node.kind {
+        NodeKind::Block { checked, body } => {
+            for statement in body.iter_mut() {
+                rewrite(statement);
+            }
+
+            *body = std::mem::take(body)
+                .into_iter()
+                .flat_map(|statement| match statement.kind {
+                    NodeKind::Block {
+                        checked: false,
+                        body: ref inner,
+                    } => inner.clone(),
+                    _ => vec![statement],
+                })
+                .collect();
+
+            if *checked && !body.iter().any(Node::returns) {
+                *checked = false;
+            }
+        }
+
+        NodeKind::Conditional(branches, else_body) => {
+            for (cond, body) in branches.iter_mut() {
+                rewrite(cond);
+                rewrite(body);
+            }
+
+            rewrite(else_body);
+
+            if let NodeKind::Conditional(inner_branches, inner_else) = &mut else_body.kind {
+                branches.append(inner_branches);
+                *else_body =
+                    std::mem::replace(inner_else, Node::new(NodeKind::Boolean(false)).into());
+            }
+        }
+
+        NodeKind::Assign(_, value)
+        | NodeKind::Return(value)
+        | NodeKind::Yield(value)
+        | NodeKind::Coroutine(value)
+        | NodeKind::Done(value)
+        | NodeKind::Throw(value) => rewrite(value),
+        NodeKind::Try {
+            body,
+            handler,
+            finally,
+            ..
+        } => {
+            rewrite(body);
+            rewrite(handler);
+            rewrite(finally);
+        }
+        NodeKind::While(lhs, rhs)
+        | NodeKind::Resume(lhs, rhs)
+        | NodeKind::BinaryOp(_, lhs, rhs) => {
+            rewrite(lhs);
+            rewrite(rhs);
+        }
+
+        NodeKind::Boolean(_)
+        | NodeKind::Float(_)
+        | NodeKind::Var(_)
+        | NodeKind::RawFloat(_)
+        | NodeKind::RawVar(_) => {}
+    }
+}
+
+#[test]
+pub fn peephole() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        Expr::Block(vec![x().assign(Expr::Float(1.0))]),
+        Binding::Global("y".into())
+            .assign(Expr::Block(vec![Expr::Return(Expr::Float(2.0).into())])),
+        Expr::Block(vec![
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0))),
+            Expr::Block(vec![]),
+        ]),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let mut optimized = compiler.clone();
+    optimized.peephole().unwrap();
+
+    assert!(optimized.future_tape.len() < compiler.future_tape.len());
+
+    let statements = match Node::decode(&optimized.future_tape).unwrap().kind {
+        NodeKind::Block {
+            checked: false,
+            body,
+        } => body,
+        kind => panic!("Expected an unchecked block, got {kind:?}"),
+    };
+
+    assert_eq!(statements.len(), 4);
+    assert!(matches!(
+        &statements[1].kind,
+        NodeKind::Assign(_, value) if matches!(value.kind, NodeKind::Block { checked: true, .. })
+    ));
+
+    for compiler in [compiler, optimized] {
+        let mut context = CallContext::new(
+            compiler.future_tape.as_ptr(),
+            compiler.future_tape.len(),
+            compiler.globals.len(),
+        );
+        context.execute();
+
+        assert_eq!(context.globals, vec![Value::Float(10.0), Value::Float(2.0)]);
+    }
+}
+
+#[test]
+pub fn peephole_conditional() {
+    use crate::expr::*;
+
+    let program = Expr::Conditional(
+        (Expr::global("a"), Expr::Float(1.0)).into(),
+        vec![],
+        Some(
+            Expr::Conditional(
+                (Expr::global("b"), Expr::Float(2.0)).into(),
+                vec![],
+                Some(Expr::Float(3.0).into()),
+            )
+            .into(),
+        ),
+    );
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+    compiler.peephole().unwrap();
+
+    match Node::decode(&compiler.future_tape).unwrap().kind {
+        NodeKind::Conditional(branches, else_body) => {
+            assert_eq!(branches.len(), 2);
+            assert_eq!(else_body.kind, NodeKind::Float(3.0));
+        }
+        kind => panic!("Expected a conditional, got {kind:?}"),
+    }
+}
@@ -0,0 +1,315 @@
+//! An operation level profiler, enabled by the `profiler` feature.
+//!
+//! Once [attached](CallContext::profile), the profiler counts how many times
+//! every operation of the tape runs and how long it takes, both in total and
+//! on its own (minus the operations it evaluated). The [report](Profiler::report)
+//! maps the hottest offsets back to their [listing](Dissassembler::listing)
+//! and [folded stacks](Profiler::write_folded) can be fed to flamegraph
+//! tools.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub count: u64,
+    /// Time spent in the operation, including the operations it evaluated.
+    pub total: Duration,
+    /// Time spent in the operation itself.
+    pub own: Duration,
+}
+
+/// An operation being executed.
+#[derive(Debug)]
+struct Frame {
+    offset: usize,
+    op: Option<OpCode>,
+    start: Instant,
+    /// Time spent in the operations it evaluated so far.
+    children: Duration,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    listing: Listing,
+    offsets: BTreeMap<usize, Sample>,
+    ops: HashMap<OpCode, Sample>,
+    /// Own time of every stack of offsets seen.
+    stacks: BTreeMap<Vec<usize>, Duration>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    /// A profiler for the program compiled by `compiler`.
+    pub fn new(compiler: &ImCompiler) -> Self {
+        Self {
+            listing: Dissassembler::from(compiler.clone()).listing(),
+            offsets: BTreeMap::new(),
+            ops: HashMap::new(),
+            stacks: BTreeMap::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Called before the operation at `offset` runs.
+    pub fn enter(&mut self, offset: usize, op: Option<OpCode>) {
+        self.frames.push(Frame {
+            offset,
+            op,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    /// Called once the last entered operation returned.
+    pub fn exit(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+
+        let total = frame.start.elapsed();
+        let own = total.saturating_sub(frame.children);
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += total;
+        }
+
+        let sample = self.offsets.entry(frame.offset).or_default();
+        sample.count += 1;
+        sample.total += total;
+        sample.own += own;
+
+        if let Some(op) = frame.op {
+            let sample = self.ops.entry(op).or_default();
+            sample.count += 1;
+            sample.own += own;
+
+            // Recursive operations are only timed by their outermost run.
+            if !self.frames.iter().any(|outer| outer.op == Some(op)) {
+                sample.total += total;
+            }
+        }
+
+        let mut stack: Vec<usize> = self.frames.iter().map(|frame| frame.offset).collect();
+        stack.push(frame.offset);
+        *self.stacks.entry(stack).or_default() += own;
+    }
+
+    /// Samples of every operation that ran, by offset.
+    pub fn offsets(&self) -> &BTreeMap<usize, Sample> {
+        &self.offsets
+    }
+
+    /// Samples of every kind of operation that ran, by decreasing own time.
+    pub fn ops(&self) -> Vec<(OpCode, Sample)> {
+        let mut ops: Vec<_> = self.ops.iter().map(|(op, sample)| (*op, *sample)).collect();
+        ops.sort_by(|a, b| b.1.own.cmp(&a.1.own).then(a.1.count.cmp(&b.1.count)));
+
+        ops
+    }
+
+    /// The `top` offsets with the most own time, hottest first.
+    pub fn hot_spots(&self, top: usize) -> Vec<(usize, Sample)> {
+        let mut offsets: Vec<_> = self
+            .offsets
+            .iter()
+            .map(|(offset, sample)| (*offset, *sample))
+            .collect();
+        offsets.sort_by(|a, b| b.1.own.cmp(&a.1.own).then(a.0.cmp(&b.0)));
+        offsets.truncate(top);
+
+        offsets
+    }
+
+    /// Forgets every sample.
+    pub fn reset(&mut self) {
+        self.offsets.clear();
+        self.ops.clear();
+        self.stacks.clear();
+    }
+
+    /// A report of the `top` hottest offsets and of every kind of operation.
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        self.write_report(&mut out, top)
+            .expect("writing to a String can't fail");
+
+        out
+    }
+
+    pub fn write_report(&self, out: &mut impl fmt::Write, top: usize) -> fmt::Result {
+        let count: u64 = self.offsets.values().map(|sample| sample.count).sum();
+        let time: Duration = self.offsets.values().map(|sample| sample.own).sum();
+
+        writeln!(out, "{count} operations in {time:?}")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:>6}  {:>8}  {:>12}  {:>12}  statement",
+            "offset", "count", "total", "own"
+        )?;
+
+        for (offset, sample) in self.hot_spots(top) {
+            write!(
+                out,
+                "{offset:>6}  {:>8}  {:>12.1?}  {:>12.1?}  ",
+                sample.count, sample.total, sample.own
+            )?;
+
+            match self.listing.line_at(offset) {
+                Some(line) => writeln!(out, "{}", self.listing.render(line))?,
+                None => writeln!(out, "?")?,
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<16}  {:>8}  {:>12}  {:>12}",
+            "op", "count", "total", "own"
+        )?;
+
+        for (op, sample) in self.ops() {
+            writeln!(
+                out,
+                "{:<16}  {:>8}  {:>12.1?}  {:>12.1?}",
+                op.name(),
+                sample.count,
+                sample.total,
+                sample.own
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the own time of every stack of operations, in nanoseconds,
+    /// in the folded format read by flamegraph tools.
+    pub fn write_folded(&self, out: &mut impl io::Write) -> io::Result<()> {
+        for (stack, own) in &self.stacks {
+            for (i, offset) in stack.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ";")?;
+                }
+
+                match self.listing.line_at(*offset) {
+                    Some(line) => write!(out, "{offset} {}", self.listing.render(line))?,
+                    None => write!(out, "{offset} ?")?,
+                }
+            }
+
+            writeln!(out, " {}", own.as_nanos())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CallContext {
+    /// Profiles every following operation, the returned handle gives
+    /// access to the samples once the program ran.
+    pub fn profile(&mut self, profiler: Profiler) -> Rc<RefCell<Profiler>> {
+        let profiler = Rc::new(RefCell::new(profiler));
+        self.profiler = Some(profiler.clone());
+
+        profiler
+    }
+}
+
+#[test]
+pub fn profiler() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    // 0 block, 2 assign x, 4 float, 6 hint, 7 while_loop, 9 lt,
+    // 10 var, 12 float, 14 assign x, 16 add, 17 var, 19 float
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    let profiler = context.profile(Profiler::new(&compiler));
+    context.execute();
+
+    let profiler = profiler.borrow();
+
+    let counts: Vec<(usize, u64)> = profiler
+        .offsets()
+        .iter()
+        .map(|(offset, sample)| (*offset, sample.count))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![
+            (0, 1),
+            (2, 1),
+            (4, 1),
+            (7, 1),
+            (9, 11),
+            (10, 11),
+            (12, 11),
+            (14, 10),
+            (16, 10),
+            (17, 10),
+            (19, 10)
+        ]
+    );
+
+    for sample in profiler.offsets().values() {
+        assert!(sample.own <= sample.total);
+    }
+
+    let root = profiler.offsets()[&0];
+    let own: Duration = profiler.offsets().values().map(|sample| sample.own).sum();
+    assert_eq!(own, root.total);
+
+    let ops: HashMap<OpCode, u64> = profiler
+        .ops()
+        .into_iter()
+        .map(|(op, sample)| (op, sample.count))
+        .collect();
+    assert_eq!(ops[&OpCode::Float], 22);
+    assert_eq!(ops[&OpCode::Assign], 11);
+    assert_eq!(ops[&OpCode::WhileLoop], 1);
+
+    let report = profiler.report(3);
+    let lines: Vec<&str> = report.lines().collect();
+    assert!(lines[0].starts_with("77 operations in "));
+    assert_eq!(lines.len(), 3 + 3 + 2 + ops.len());
+
+    for line in &lines[3..6] {
+        let offset: usize = line.split_whitespace().next().unwrap().parse().unwrap();
+        let statement = profiler
+            .listing
+            .render(profiler.listing.line_at(offset).unwrap());
+        assert!(line.ends_with(&statement));
+    }
+
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+
+    assert!(folded.lines().any(|line| line.starts_with(
+        "0 block -> 21 (end);7 while_loop -> 21 (end);14 assign x;16 add;19 float 1.0 "
+    )));
+    assert_eq!(folded.lines().count(), 11);
+}
@@ -0,0 +1,208 @@
+//! Execution tracing, enabled by the `tracing` feature.
+//!
+//! Once a [`Tracer`] is [attached](CallContext::trace), every operation
+//! reports an [`Event`] when it returns, so events of nested operations
+//! come before the event of the operation evaluating them. Without the
+//! feature nothing is compiled into the interpreter.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub offset: usize,
+    /// Number of operations this one is nested in.
+    pub depth: usize,
+    pub op: Option<OpCode>,
+    /// Operands stored on the tape right after the operation.
+    pub arguments: Vec<Argument>,
+    /// Values of the operations this one evaluated, in order.
+    pub operands: Vec<Value>,
+    pub result: Option<Value>,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}  {:ident$}",
+            self.offset,
+            "",
+            ident = self.depth * 2
+        )?;
+
+        match self.op {
+            Some(op) => write!(f, "{}", op.name())?,
+            None => write!(f, "?")?,
+        }
+
+        for argument in &self.arguments {
+            match argument {
+                Argument::Float(x) => write!(f, " {x:?}")?,
+                Argument::Global(idx) => write!(f, " #{idx}")?,
+                Argument::Jump(target) => write!(f, " -> {target}")?,
+                Argument::Count(count) => write!(f, " {count}")?,
+            }
+        }
+
+        if !self.operands.is_empty() {
+            write!(f, " {:?}", self.operands)?;
+        }
+
+        match self.result {
+            Some(result) => write!(f, " = {result:?}"),
+            None => Ok(()),
+        }
+    }
+}
+
+pub trait Tracer {
+    fn trace(&mut self, event: &Event);
+}
+
+/// Keeps a handle on a tracer while it's attached.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn trace(&mut self, event: &Event) {
+        self.borrow_mut().trace(event)
+    }
+}
+
+/// Records every event.
+#[derive(Debug, Clone, Default)]
+pub struct TraceLog {
+    pub events: Vec<Event>,
+}
+
+impl Tracer for TraceLog {
+    fn trace(&mut self, event: &Event) {
+        self.events.push(event.clone());
+    }
+}
+
+/// Writes every event on its own line.
+pub struct TracePrinter<W>(pub W);
+
+impl<W: Write> Tracer for TracePrinter<W> {
+    fn trace(&mut self, event: &Event) {
+        let _ = writeln!(self.0, "{event}");
+    }
+}
+
+/// The tracer attached to a context, along with the values returned to
+/// the operations being executed.
+pub struct Tracing {
+    tracer: Box<dyn Tracer>,
+    operands: Vec<Vec<Value>>,
+}
+
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracing")
+            .field("operands", &self.operands)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tracing {
+    /// Called before an operation runs.
+    pub fn enter(&mut self) {
+        self.operands.push(Vec::new());
+    }
+
+    /// Called instead of [`Self::exit`] when the operation unwound because
+    /// the program halted.
+    pub fn unwind(&mut self) {
+        self.operands.pop();
+    }
+
+    /// Called once the operation at `offset` of `tape` returned `result`.
+    pub fn exit(&mut self, offset: usize, op: Option<OpCode>, tape: &[u64], result: Option<Value>) {
+        let operands = self.operands.pop().unwrap_or_default();
+
+        if let (Some(parent), Some(result)) = (self.operands.last_mut(), result) {
+            parent.push(result);
+        }
+
+        let mut arguments = Vec::new();
+        let layout = op.map_or(&[][..], OpCode::operands);
+
+        for (operand, cell) in layout.iter().zip(&tape[offset + 1..]) {
+            arguments.push(match operand {
+                Operand::Float => Argument::Float(f64::from_bits(*cell)),
+                Operand::Global => Argument::Global(*cell),
+                Operand::Jump => Argument::Jump(*cell),
+                Operand::Count => Argument::Count(*cell),
+                _ => break,
+            });
+        }
+
+        self.tracer.trace(&Event {
+            offset,
+            depth: self.operands.len(),
+            op,
+            arguments,
+            operands,
+            result,
+        });
+    }
+}
+
+impl CallContext {
+    /// Reports every following operation to `tracer`.
+    pub fn trace(&mut self, tracer: impl Tracer + 'static) {
+        self.tracing = Some(Rc::new(RefCell::new(Tracing {
+            tracer: Box::new(tracer),
+            operands: Vec::new(),
+        })));
+    }
+}
+
+#[test]
+pub fn tracer() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(1.0).op(Operator::Add, Expr::Float(2.0))),
+        Expr::Return(x().var().op(Operator::Gt, Expr::Float(2.0)).into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    let log = Rc::new(RefCell::new(TraceLog::default()));
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.trace(log.clone());
+    context.execute();
+
+    let trace: Vec<String> = log
+        .borrow()
+        .events
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    assert_eq!(
+        trace,
+        vec![
+            "    5        float 1.0 = Float(1.0)",
+            "    7        float 2.0 = Float(2.0)",
+            "    4      add [Float(1.0), Float(2.0)] = Float(3.0)",
+            "    2    assign #0 [Float(3.0)] = Nil",
+            "   11      var #0 = Float(3.0)",
+            "   13      float 2.0 = Float(2.0)",
+            "   10    gt [Float(3.0), Float(2.0)] = Boolean(true)",
+            "    0  block_checked -> 15 [Nil, Boolean(true)] = Boolean(true)",
+        ]
+    );
+}
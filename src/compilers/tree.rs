@@ -0,0 +1,500 @@
+use std::fmt;
+
+use crate::*;
+
+/// A decoded tape instruction along with the instructions it evaluates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    /// Offset of the instruction's first cell (its hint, if it has one)
+    /// on the tape it was decoded from.
+    pub offset: usize,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Boolean(bool),
+    Float(f64),
+    Var(u64),
+    RawFloat(f64),
+    RawVar(u64),
+    Assign(u64, Box<Node>),
+    Block {
+        checked: bool,
+        body: Vec<Node>,
+    },
+    Return(Box<Node>),
+    Yield(Box<Node>),
+    Coroutine(Box<Node>),
+    Resume(Box<Node>, Box<Node>),
+    Done(Box<Node>),
+    Throw(Box<Node>),
+    Try {
+        body: Box<Node>,
+        binding: u64,
+        handler: Box<Node>,
+        finally: Box<Node>,
+    },
+    While(Box<Node>, Box<Node>),
+    Conditional(Vec<(Node, Node)>, Box<Node>),
+    BinaryOp(OpCode, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownOp { offset: usize, cell: u64 },
+    UnexpectedOp { offset: usize, op: OpCode },
+    InvalidHint { offset: usize, hint: Hint },
+    BadJump { offset: usize, target: u64 },
+    TrailingCells { offset: usize },
+    UnknownGlobal { offset: usize, index: u64 },
+    TooDeep { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of tape"),
+            DecodeError::UnknownOp { offset, cell } => {
+                write!(f, "{offset}: unknown operation {cell:#x}")
+            }
+            DecodeError::UnexpectedOp { offset, op } => {
+                write!(f, "{offset}: unexpected operation {}", op.name())
+            }
+            DecodeError::InvalidHint { offset, hint } => {
+                write!(f, "{offset}: invalid hint {hint:?}")
+            }
+            DecodeError::BadJump { offset, target } => {
+                write!(
+                    f,
+                    "{offset}: jump to {target} doesn't land after the construct"
+                )
+            }
+            DecodeError::TrailingCells { offset } => write!(f, "{offset}: trailing cells"),
+            DecodeError::UnknownGlobal { offset, index } => {
+                write!(f, "{offset}: unknown global {index}")
+            }
+            DecodeError::TooDeep { offset } => write!(f, "{offset}: nested too deeply"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Node {
+    /// How deeply instructions can be nested, tape readers reject deeper
+    /// ones instead of overflowing the stack.
+    pub const MAX_DEPTH: usize = 1024;
+
+    pub fn new(kind: NodeKind) -> Self {
+        Self { offset: 0, kind }
+    }
+
+    /// Decodes the program stored on `tape`, which must hold exactly one
+    /// top-level instruction.
+    pub fn decode(tape: &[u64]) -> Result<Node, DecodeError> {
+        Self::decode_with(tape, OpCode::from_address)
+    }
+
+    /// Decodes a tape whose operation cells are turned into operations
+    /// by `resolve`, e.g. [`OpCode::from_id`] for encoded tapes.
+    pub fn decode_with(
+        tape: &[u64],
+        resolve: fn(u64) -> Option<OpCode>,
+    ) -> Result<Node, DecodeError> {
+        let mut decoder = Decoder {
+            tape,
+            offset: 0,
+            depth: 0,
+            resolve,
+        };
+        let node = decoder.node(false)?;
+
+        if decoder.offset != tape.len() {
+            return Err(DecodeError::TrailingCells {
+                offset: decoder.offset,
+            });
+        }
+
+        Ok(node)
+    }
+
+    /// Appends the instruction to `tape`, recomputing every jump target.
+    pub fn emit(&self, tape: &mut Vec<u64>) {
+        self.emit_with(tape, OpCode::address)
+    }
+
+    /// Like [`Self::emit`] with operation cells produced by `encode`, e.g.
+    /// [`OpCode::id`] for encoded tapes.
+    pub fn emit_with(&self, tape: &mut Vec<u64>, encode: fn(OpCode) -> u64) {
+        match &self.kind {
+            NodeKind::Boolean(true) => tape.push(encode(OpCode::True)),
+            NodeKind::Boolean(false) => tape.push(encode(OpCode::False)),
+            NodeKind::Float(x) => {
+                tape.push(encode(OpCode::Float));
+                tape.push(x.to_bits());
+            }
+            NodeKind::Var(idx) => {
+                tape.push(encode(OpCode::Var));
+                tape.push(*idx);
+            }
+            NodeKind::RawFloat(x) => {
+                tape.push(encode(OpCode::RawFloat));
+                tape.push(x.to_bits());
+            }
+            NodeKind::RawVar(idx) => {
+                tape.push(encode(OpCode::RawVar));
+                tape.push(*idx);
+            }
+            NodeKind::Assign(idx, value) => {
+                tape.push(encode(OpCode::Assign));
+                tape.push(*idx);
+                value.emit_with(tape, encode);
+            }
+            NodeKind::Block { checked, body } => {
+                tape.push(if *checked {
+                    encode(OpCode::BlockChecked)
+                } else {
+                    encode(OpCode::Block)
+                });
+
+                let next_instr = tape.len();
+                tape.push(0);
+
+                for statement in body {
+                    statement.emit_with(tape, encode);
+                }
+
+                tape[next_instr] = tape.len() as u64;
+            }
+            NodeKind::Return(value) => {
+                tape.push(Hint::Return.cell());
+                value.emit_with(tape, encode);
+            }
+            NodeKind::Yield(value) => {
+                tape.push(encode(OpCode::Yield));
+                value.emit_with(tape, encode);
+            }
+            NodeKind::Coroutine(body) => {
+                tape.push(encode(OpCode::Coroutine));
+
+                let next_instr = tape.len();
+                tape.push(0);
+
+                body.emit_with(tape, encode);
+
+                tape[next_instr] = tape.len() as u64;
+            }
+            NodeKind::Resume(coroutine, value) => {
+                tape.push(encode(OpCode::Resume));
+                coroutine.emit_with(tape, encode);
+                value.emit_with(tape, encode);
+            }
+            NodeKind::Done(coroutine) => {
+                tape.push(encode(OpCode::Done));
+                coroutine.emit_with(tape, encode);
+            }
+            NodeKind::Throw(exception) => {
+                tape.push(encode(OpCode::Throw));
+                exception.emit_with(tape, encode);
+            }
+            NodeKind::Try {
+                body,
+                binding,
+                handler,
+                finally,
+            } => {
+                tape.push(encode(OpCode::Try));
+
+                let handler_instr = tape.len();
+                tape.push(0);
+                let finally_instr = tape.len();
+                tape.push(0);
+                tape.push(*binding);
+
+                body.emit_with(tape, encode);
+                tape[handler_instr] = tape.len() as u64;
+                handler.emit_with(tape, encode);
+                tape[finally_instr] = tape.len() as u64;
+                finally.emit_with(tape, encode);
+            }
+            NodeKind::While(cond, body) => {
+                tape.push(Hint::While.cell());
+                tape.push(encode(OpCode::WhileLoop));
+
+                let next_instr = tape.len();
+                tape.push(0);
+
+                cond.emit_with(tape, encode);
+                body.emit_with(tape, encode);
+
+                tape[next_instr] = tape.len() as u64;
+            }
+            NodeKind::Conditional(branches, else_body) => {
+                tape.push(encode(OpCode::Conditional));
+                tape.push(branches.len() as u64);
+
+                let end_fix_idx = tape.len();
+                tape.push(0);
+
+                for (cond, body) in branches {
+                    let false_fix_idx = tape.len();
+                    tape.push(0);
+
+                    cond.emit_with(tape, encode);
+                    body.emit_with(tape, encode);
+
+                    tape[false_fix_idx] = tape.len() as u64;
+                }
+
+                else_body.emit_with(tape, encode);
+                tape[end_fix_idx] = tape.len() as u64;
+            }
+            NodeKind::BinaryOp(op, lhs, rhs) => {
+                tape.push(encode(*op));
+                lhs.emit_with(tape, encode);
+                rhs.emit_with(tape, encode);
+            }
+        }
+    }
+
+    /// The instructions evaluated by this one, in tape order.
+    pub fn children(&self) -> Vec<&Node> {
+        match &self.kind {
+            NodeKind::Assign(_, value)
+            | NodeKind::Return(value)
+            | NodeKind::Yield(value)
+            | NodeKind::Coroutine(value)
+            | NodeKind::Done(value)
+            | NodeKind::Throw(value) => vec![value],
+            NodeKind::While(lhs, rhs)
+            | NodeKind::Resume(lhs, rhs)
+            | NodeKind::BinaryOp(_, lhs, rhs) => vec![lhs, rhs],
+            NodeKind::Block { body, .. } => body.iter().collect(),
+            NodeKind::Try {
+                body,
+                handler,
+                finally,
+                ..
+            } => vec![body, handler, finally],
+            NodeKind::Conditional(branches, else_body) => branches
+                .iter()
+                .flat_map(|(cond, body)| [cond, body])
+                .chain(std::iter::once(&**else_body))
+                .collect(),
+            NodeKind::Boolean(_)
+            | NodeKind::Float(_)
+            | NodeKind::Var(_)
+            | NodeKind::RawFloat(_)
+            | NodeKind::RawVar(_) => vec![],
+        }
+    }
+
+    /// Whether this instruction is preceded by a hint on the tape.
+    pub fn is_hinted(&self) -> bool {
+        matches!(self.kind, NodeKind::Return(_) | NodeKind::While(_, _))
+    }
+
+    /// Whether this statement can return from the block it's in, from any
+    /// depth of the blocks, loops and conditionals used as statements it's
+    /// made of.
+    pub fn returns(&self) -> bool {
+        match &self.kind {
+            NodeKind::Return(_) => true,
+            NodeKind::Block {
+                checked: false,
+                body,
+            } => body.iter().any(Node::returns),
+            NodeKind::While(_, body) => body.returns(),
+            NodeKind::Conditional(branches, else_body) => {
+                branches.iter().any(|(_, body)| body.returns()) || else_body.returns()
+            }
+            _ => false,
+        }
+    }
+}
+
+struct Decoder<'a> {
+    tape: &'a [u64],
+    offset: usize,
+    depth: usize,
+    resolve: fn(u64) -> Option<OpCode>,
+}
+
+impl<'a> Decoder<'a> {
+    fn read(&mut self) -> Result<u64, DecodeError> {
+        let cell = *self
+            .tape
+            .get(self.offset)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.offset += 1;
+
+        Ok(cell)
+    }
+
+    fn op(&mut self) -> Result<OpCode, DecodeError> {
+        let offset = self.offset;
+        let cell = self.read()?;
+
+        match Instr::decode(cell, self.resolve, false) {
+            Ok(Instr::Op(op)) => Ok(op),
+            _ => Err(DecodeError::UnknownOp { offset, cell }),
+        }
+    }
+
+    /// Checks that a construct's jump target is the offset right after it.
+    fn landed(&self, offset: usize, target: u64) -> Result<(), DecodeError> {
+        if target as usize != self.offset {
+            return Err(DecodeError::BadJump { offset, target });
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the instruction at the current offset, hinted only if it's
+    /// a `statement`.
+    fn node(&mut self, statement: bool) -> Result<Node, DecodeError> {
+        if self.depth == Node::MAX_DEPTH {
+            return Err(DecodeError::TooDeep {
+                offset: self.offset,
+            });
+        }
+
+        self.depth += 1;
+        let node = self.instr(statement)?;
+        self.depth -= 1;
+
+        Ok(node)
+    }
+
+    fn instr(&mut self, statement: bool) -> Result<Node, DecodeError> {
+        let offset = self.offset;
+        let cell = self.read()?;
+
+        let op = match Instr::decode(cell, self.resolve, statement) {
+            Ok(Instr::Op(op)) => op,
+            Err(InstrError::UnknownOp) => return Err(DecodeError::UnknownOp { offset, cell }),
+            Err(InstrError::InvalidHint(hint)) => {
+                return Err(DecodeError::InvalidHint { offset, hint })
+            }
+            Ok(Instr::Hint(hint)) => {
+                let kind = match hint {
+                    Hint::Return => NodeKind::Return(self.node(false)?.into()),
+                    Hint::While => {
+                        let op_offset = self.offset;
+                        let op = self.op()?;
+
+                        if op != OpCode::WhileLoop {
+                            return Err(DecodeError::UnexpectedOp {
+                                offset: op_offset,
+                                op,
+                            });
+                        }
+
+                        let next_instr = self.read()?;
+                        let cond = self.node(false)?;
+                        let body = self.node(true)?;
+                        self.landed(op_offset + 1, next_instr)?;
+
+                        NodeKind::While(cond.into(), body.into())
+                    }
+                    Hint::Break => return Err(DecodeError::InvalidHint { offset, hint }),
+                };
+
+                return Ok(Node { offset, kind });
+            }
+        };
+
+        let kind = match op {
+            OpCode::True => NodeKind::Boolean(true),
+            OpCode::False => NodeKind::Boolean(false),
+            OpCode::Float => NodeKind::Float(f64::from_bits(self.read()?)),
+            OpCode::Var => NodeKind::Var(self.read()?),
+            OpCode::RawFloat => NodeKind::RawFloat(f64::from_bits(self.read()?)),
+            OpCode::RawVar => NodeKind::RawVar(self.read()?),
+            OpCode::Assign => {
+                let idx = self.read()?;
+                NodeKind::Assign(idx, self.node(false)?.into())
+            }
+            OpCode::Block | OpCode::BlockChecked => {
+                let next_instr = self.read()?;
+                let mut body = Vec::new();
+
+                while (self.offset as u64) < next_instr {
+                    body.push(self.node(true)?);
+                }
+
+                self.landed(offset + 1, next_instr)?;
+
+                NodeKind::Block {
+                    checked: op == OpCode::BlockChecked,
+                    body,
+                }
+            }
+            OpCode::Yield => NodeKind::Yield(self.node(false)?.into()),
+            OpCode::Coroutine => {
+                let next_instr = self.read()?;
+                let body = self.node(false)?;
+                self.landed(offset + 1, next_instr)?;
+
+                NodeKind::Coroutine(body.into())
+            }
+            OpCode::Resume => {
+                let coroutine = self.node(false)?;
+                let value = self.node(false)?;
+
+                NodeKind::Resume(coroutine.into(), value.into())
+            }
+            OpCode::Done => NodeKind::Done(self.node(false)?.into()),
+            OpCode::Throw => NodeKind::Throw(self.node(false)?.into()),
+            OpCode::Try => {
+                let handler_instr = self.read()?;
+                let finally_instr = self.read()?;
+                let binding = self.read()?;
+
+                let body = self.node(false)?;
+                self.landed(offset + 1, handler_instr)?;
+                let handler = self.node(false)?;
+                self.landed(offset + 2, finally_instr)?;
+                let finally = self.node(false)?;
+
+                NodeKind::Try {
+                    body: body.into(),
+                    binding,
+                    handler: handler.into(),
+                    finally: finally.into(),
+                }
+            }
+            OpCode::WhileLoop => return Err(DecodeError::UnexpectedOp { offset, op }),
+            OpCode::Conditional => {
+                let branch_amount = self.read()?;
+                let end_jmp = self.read()?;
+                let mut branches = Vec::new();
+
+                for _ in 0..branch_amount {
+                    let false_offset = self.offset;
+                    let if_false_jmp = self.read()?;
+                    let cond = self.node(false)?;
+                    let body = self.node(true)?;
+                    self.landed(false_offset, if_false_jmp)?;
+
+                    branches.push((cond, body));
+                }
+
+                let else_body = self.node(true)?;
+                self.landed(offset + 2, end_jmp)?;
+
+                NodeKind::Conditional(branches, else_body.into())
+            }
+            op => {
+                let lhs = self.node(false)?;
+                let rhs = self.node(false)?;
+
+                NodeKind::BinaryOp(op, lhs.into(), rhs.into())
+            }
+        };
+
+        Ok(Node { offset, kind })
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+
+/// An error raised by an operation, thrown as a `Value::Error` that scripts
+/// can catch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum RuntimeError {
+    /// An arithmetic operation on something else than floats.
+    InvalidOperands = 1,
+    /// A global index past the context's globals.
+    UnknownGlobal = 2,
+    /// A coroutine operation on something else than a coroutine.
+    NotACoroutine = 3,
+    /// A coroutine resuming itself.
+    CoroutineRunning = 4,
+}
+
+impl RuntimeError {
+    pub const ALL: [RuntimeError; 4] = [
+        RuntimeError::InvalidOperands,
+        RuntimeError::UnknownGlobal,
+        RuntimeError::NotACoroutine,
+        RuntimeError::CoroutineRunning,
+    ];
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<RuntimeError> {
+        RuntimeError::ALL
+            .into_iter()
+            .find(|error| error.code() == code)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::InvalidOperands => write!(f, "invalid operands"),
+            RuntimeError::UnknownGlobal => write!(f, "unknown global"),
+            RuntimeError::NotACoroutine => write!(f, "not a coroutine"),
+            RuntimeError::CoroutineRunning => write!(f, "coroutine is already running"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Float(f64),
+    /// Handle of a coroutine created by the running context.
+    Coroutine(u32),
+    Error(RuntimeError),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Boolean(b) => *b,
+            Value::Float(f) => *f == 1.0,
+            Value::Coroutine(_) => true,
+            Value::Error(_) => false,
+        }
+    }
+
+    pub fn as_float(self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn as_coroutine(self) -> Option<u32> {
+        match self {
+            Value::Coroutine(idx) => Some(idx),
+            _ => None,
+        }
+    }
+
+    pub fn as_error(self) -> Option<RuntimeError> {
+        match self {
+            Value::Error(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxing::Value;
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxing {
+    use std::cmp::Ordering;
+    use std::fmt;
+
+    use super::RuntimeError;
+
+    const TAG_MASK: u64 = 0xffff_0000_0000_0000;
+    const TAG_NIL: u64 = 0xfff9_0000_0000_0000;
+    const TAG_BOOLEAN: u64 = 0xfffa_0000_0000_0000;
+    const TAG_COROUTINE: u64 = 0xfffb_0000_0000_0000;
+    const TAG_ERROR: u64 = 0xfffc_0000_0000_0000;
+    const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+    /// A `Value` packed in a single word. Floats are stored as is (NaNs are
+    /// canonicalized), every other value lives in the payload of a negative
+    /// quiet NaN that no float can take.
+    ///
+    /// `Value::Nil`, `Value::Boolean(..)`, `Value::Float(..)`,
+    /// `Value::Coroutine(..)` and `Value::Error(..)` keep working as
+    /// constructors, but they can't be used as patterns: use
+    /// [`Value::as_float`], [`Value::as_bool`], [`Value::as_coroutine`],
+    /// [`Value::as_error`] and [`Value::is_nil`] instead.
+    #[derive(Clone, Copy)]
+    #[repr(transparent)]
+    pub struct Value(u64);
+
+    #[allow(non_upper_case_globals, non_snake_case)]
+    impl Value {
+        pub const Nil: Value = Value(TAG_NIL);
+
+        pub fn Boolean(b: bool) -> Value {
+            Value(TAG_BOOLEAN | b as u64)
+        }
+
+        pub fn Float(f: f64) -> Value {
+            if f.is_nan() {
+                Value(CANONICAL_NAN)
+            } else {
+                Value(f.to_bits())
+            }
+        }
+
+        pub fn Coroutine(idx: u32) -> Value {
+            Value(TAG_COROUTINE | idx as u64)
+        }
+
+        pub fn Error(error: RuntimeError) -> Value {
+            Value(TAG_ERROR | error.code() as u64)
+        }
+    }
+
+    impl Value {
+        pub fn truthy(&self) -> bool {
+            match self.unbox() {
+                Unboxed::Nil => false,
+                Unboxed::Boolean(b) => b,
+                Unboxed::Float(f) => f == 1.0,
+                Unboxed::Coroutine(_) => true,
+                Unboxed::Error(_) => false,
+            }
+        }
+
+        #[inline]
+        pub fn as_float(self) -> Option<f64> {
+            if self.0 & TAG_MASK < TAG_NIL {
+                Some(f64::from_bits(self.0))
+            } else {
+                None
+            }
+        }
+
+        #[inline]
+        pub fn as_bool(self) -> Option<bool> {
+            if self.0 & TAG_MASK == TAG_BOOLEAN {
+                Some(self.0 & 1 == 1)
+            } else {
+                None
+            }
+        }
+
+        #[inline]
+        pub fn is_nil(self) -> bool {
+            self.0 == TAG_NIL
+        }
+
+        #[inline]
+        pub fn as_coroutine(self) -> Option<u32> {
+            if self.0 & TAG_MASK == TAG_COROUTINE {
+                Some(self.0 as u32)
+            } else {
+                None
+            }
+        }
+
+        #[inline]
+        pub fn as_error(self) -> Option<RuntimeError> {
+            if self.0 & TAG_MASK == TAG_ERROR {
+                RuntimeError::from_code(self.0 as u8)
+            } else {
+                None
+            }
+        }
+
+        fn unbox(self) -> Unboxed {
+            if let Some(f) = self.as_float() {
+                Unboxed::Float(f)
+            } else if let Some(b) = self.as_bool() {
+                Unboxed::Boolean(b)
+            } else if let Some(idx) = self.as_coroutine() {
+                Unboxed::Coroutine(idx)
+            } else if let Some(error) = self.as_error() {
+                Unboxed::Error(error)
+            } else {
+                Unboxed::Nil
+            }
+        }
+    }
+
+    /// Mirrors the unboxed `Value` so both representations compare and
+    /// print the same way.
+    #[derive(Debug, PartialEq, PartialOrd)]
+    enum Unboxed {
+        Nil,
+        Boolean(bool),
+        Float(f64),
+        Coroutine(u32),
+        Error(RuntimeError),
+    }
+
+    impl PartialEq for Value {
+        fn eq(&self, other: &Self) -> bool {
+            self.unbox() == other.unbox()
+        }
+    }
+
+    impl PartialOrd for Value {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            self.unbox().partial_cmp(&other.unbox())
+        }
+    }
+
+    impl fmt::Debug for Value {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.unbox().fmt(f)
+        }
+    }
+
+    #[test]
+    pub fn nan_boxing() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+
+        for f in [0.0, -0.0, 1.5, f64::INFINITY, f64::NEG_INFINITY, f64::MIN] {
+            assert_eq!(
+                Value::Float(f).as_float().map(f64::to_bits),
+                Some(f.to_bits())
+            );
+        }
+
+        let nan = Value::Float(-f64::NAN);
+        assert!(nan.as_float().unwrap().is_nan());
+        assert_ne!(nan, nan);
+
+        assert_eq!(Value::Boolean(true).as_bool(), Some(true));
+        assert_eq!(Value::Boolean(false).as_float(), None);
+        assert!(Value::Nil.is_nil());
+        assert!(Value::Nil < Value::Boolean(false));
+        assert!(Value::Boolean(true) < Value::Float(-1.0));
+        assert_eq!(Value::Coroutine(3).as_coroutine(), Some(3));
+        assert_eq!(Value::Coroutine(3).as_float(), None);
+        assert!(Value::Float(f64::MAX) < Value::Coroutine(0));
+        assert_eq!(
+            Value::Error(RuntimeError::UnknownGlobal).as_error(),
+            Some(RuntimeError::UnknownGlobal)
+        );
+        assert_eq!(Value::Coroutine(2).as_error(), None);
+        assert_eq!(format!("{:?}", Value::Float(2.0)), "Float(2.0)");
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    UnexpectedEnd,
+    UnknownOp { offset: usize, cell: u64 },
+    UnexpectedOp { offset: usize, op: OpCode },
+    InvalidHint { offset: usize, hint: Hint },
+    BadJump { offset: usize, target: u64 },
+    BadGlobal { offset: usize, index: u64 },
+    TrailingCells { offset: usize },
+    TooDeep { offset: usize },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::UnexpectedEnd => write!(f, "unexpected end of tape"),
+            VerifyError::UnknownOp { offset, cell } => {
+                write!(f, "{offset}: unknown operation {cell:#x}")
+            }
+            VerifyError::UnexpectedOp { offset, op } => {
+                write!(f, "{offset}: unexpected operation {}", op.name())
+            }
+            VerifyError::InvalidHint { offset, hint } => {
+                write!(f, "{offset}: invalid hint {hint:?}")
+            }
+            VerifyError::BadJump { offset, target } => {
+                write!(
+                    f,
+                    "{offset}: jump to {target} doesn't land on an instruction"
+                )
+            }
+            VerifyError::BadGlobal { offset, index } => {
+                write!(f, "{offset}: unknown global {index}")
+            }
+            VerifyError::TrailingCells { offset } => write!(f, "{offset}: trailing cells"),
+            VerifyError::TooDeep { offset } => write!(f, "{offset}: nested too deeply"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl ImCompiler {
+    /// Checks that the compiled tape is safe to execute, see [`verify`].
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        verify(&self.future_tape, self.globals.len())
+    }
+}
+
+/// Walks `tape` following the [operands](OpCode::operands) of every
+/// operation and checks that:
+///
+/// - every operation cell is a known operation, of the kind its position
+///   expects (`Value` or raw `f64`)
+/// - hints only precede statements, and the instructions they apply to
+/// - every jump target is in bounds and lands on a `Value` instruction, or
+///   on the end of the tape
+/// - global indices are below `globals`
+/// - instructions are nested at most [`Node::MAX_DEPTH`] deep
+pub fn verify(tape: &[u64], globals: usize) -> Result<(), VerifyError> {
+    let mut verifier = Verifier {
+        tape,
+        offset: 0,
+        globals,
+        depth: 0,
+        starts: HashSet::new(),
+        jumps: Vec::new(),
+    };
+
+    verifier.instr(Operand::Value)?;
+
+    if verifier.offset != tape.len() {
+        return Err(VerifyError::TrailingCells {
+            offset: verifier.offset,
+        });
+    }
+
+    verifier.starts.insert(tape.len());
+
+    for (offset, target) in verifier.jumps {
+        if !verifier.starts.contains(&(target as usize)) {
+            return Err(VerifyError::BadJump { offset, target });
+        }
+    }
+
+    Ok(())
+}
+
+struct Verifier<'a> {
+    tape: &'a [u64],
+    offset: usize,
+    globals: usize,
+    depth: usize,
+    /// Offsets of every `Value` instruction's first cell.
+    starts: HashSet<usize>,
+    jumps: Vec<(usize, u64)>,
+}
+
+impl Verifier<'_> {
+    fn read(&mut self) -> Result<u64, VerifyError> {
+        let cell = *self
+            .tape
+            .get(self.offset)
+            .ok_or(VerifyError::UnexpectedEnd)?;
+        self.offset += 1;
+
+        Ok(cell)
+    }
+
+    fn op(&mut self) -> Result<OpCode, VerifyError> {
+        let offset = self.offset;
+        let cell = self.read()?;
+
+        match Instr::decode(cell, OpCode::from_address, false) {
+            Ok(Instr::Op(op)) => Ok(op),
+            _ => Err(VerifyError::UnknownOp { offset, cell }),
+        }
+    }
+
+    fn jump(&mut self) -> Result<u64, VerifyError> {
+        let offset = self.offset;
+        let target = self.read()?;
+
+        if target as usize > self.tape.len() {
+            return Err(VerifyError::BadJump { offset, target });
+        }
+
+        self.jumps.push((offset, target));
+
+        Ok(target)
+    }
+
+    /// Checks the instruction filling an operand of kind `operand`.
+    fn instr(&mut self, operand: Operand) -> Result<(), VerifyError> {
+        let offset = self.offset;
+
+        if self.depth == Node::MAX_DEPTH {
+            return Err(VerifyError::TooDeep { offset });
+        }
+
+        self.depth += 1;
+        self.instr_at(offset, operand)?;
+        self.depth -= 1;
+
+        Ok(())
+    }
+
+    fn instr_at(&mut self, offset: usize, operand: Operand) -> Result<(), VerifyError> {
+        let raw = operand == Operand::Raw;
+
+        // Raw operations can't be jumped to, they'd run as `Value` ones.
+        if !raw {
+            self.starts.insert(offset);
+        }
+
+        let cell = self.read()?;
+
+        let op = match Instr::decode(cell, OpCode::from_address, operand == Operand::Statement) {
+            Ok(Instr::Op(op)) => op,
+            Err(InstrError::UnknownOp) => return Err(VerifyError::UnknownOp { offset, cell }),
+            Err(InstrError::InvalidHint(hint)) => {
+                return Err(VerifyError::InvalidHint { offset, hint })
+            }
+            Ok(Instr::Hint(Hint::While)) => {
+                return match self.op()? {
+                    OpCode::WhileLoop => self.operands(OpCode::WhileLoop),
+                    op => Err(VerifyError::UnexpectedOp {
+                        offset: offset + 1,
+                        op,
+                    }),
+                }
+            }
+            Ok(Instr::Hint(Hint::Return)) => return self.instr(Operand::Value),
+            Ok(Instr::Hint(hint @ Hint::Break)) => {
+                return Err(VerifyError::InvalidHint { offset, hint })
+            }
+        };
+
+        if op == OpCode::WhileLoop || op.is_raw() != raw {
+            return Err(VerifyError::UnexpectedOp { offset, op });
+        }
+
+        self.operands(op)
+    }
+
+    fn operands(&mut self, op: OpCode) -> Result<(), VerifyError> {
+        let mut count = 0;
+        let mut target = None;
+
+        for operand in op.operands() {
+            match operand {
+                Operand::Float => {
+                    self.read()?;
+                }
+                Operand::Global => {
+                    let offset = self.offset;
+                    let index = self.read()?;
+
+                    if index as usize >= self.globals {
+                        return Err(VerifyError::BadGlobal { offset, index });
+                    }
+                }
+                Operand::Jump => target = Some((self.offset, self.jump()?)),
+                Operand::Value | Operand::Raw | Operand::Statement => self.instr(*operand)?,
+                Operand::Body => {
+                    let (offset, target) = target.expect("a body follows a jump");
+
+                    while (self.offset as u64) < target {
+                        self.instr(Operand::Statement)?;
+                    }
+
+                    if self.offset as u64 != target {
+                        return Err(VerifyError::BadJump { offset, target });
+                    }
+                }
+                Operand::Count => count = self.read()?,
+                Operand::Branch => {
+                    for _ in 0..count {
+                        self.jump()?;
+                        self.instr(Operand::Value)?;
+                        self.instr(Operand::Statement)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+pub fn verifier() {
+    use crate::expr::*;
+
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Expr::Conditional(
+            (x().var(), Expr::Return(Expr::Float(1.0).into())).into(),
+            vec![],
+            Some(Expr::Float(2.0).into()),
+        ),
+    ]);
+
+    let types = passes::check_types(&program).unwrap();
+    let mut compiler = ImCompiler::with_types(types);
+    compiler.compile_expr(program);
+
+    assert_eq!(compiler.verify(), Ok(()));
+
+    let tape = &compiler.future_tape;
+    let len = tape.len() as u64;
+
+    let patched = |offset: usize, cell: u64| {
+        let mut tape = tape.clone();
+        tape[offset] = cell;
+        verify(&tape, 1)
+    };
+
+    // `block` end, `assign` global, then the `while_loop` header.
+    assert_eq!(
+        patched(1, len + 1),
+        Err(VerifyError::BadJump {
+            offset: 1,
+            target: len + 1
+        })
+    );
+    assert_eq!(
+        patched(3, 1),
+        Err(VerifyError::BadGlobal {
+            offset: 3,
+            index: 1
+        })
+    );
+    assert_eq!(
+        patched(8, 11),
+        Err(VerifyError::BadJump {
+            offset: 8,
+            target: 11
+        })
+    );
+    assert_eq!(
+        patched(0, 42),
+        Err(VerifyError::UnknownOp {
+            offset: 0,
+            cell: 42
+        })
+    );
+    assert_eq!(
+        patched(4, OpCode::RawFloat.address()),
+        Err(VerifyError::UnexpectedOp {
+            offset: 4,
+            op: OpCode::RawFloat
+        })
+    );
+    assert_eq!(
+        verify(&tape[..tape.len() - 1], 1),
+        Err(VerifyError::BadJump {
+            offset: 1,
+            target: len
+        })
+    );
+
+    // The loop's end landing on the raw operand of its condition.
+    let header = tape
+        .iter()
+        .position(|cell| *cell == OpCode::WhileLoop.address());
+    let raw = tape
+        .iter()
+        .position(|cell| *cell == OpCode::RawVar.address());
+    let (jump, raw) = (header.unwrap() + 1, raw.unwrap() as u64);
+
+    assert_eq!(
+        patched(jump, raw),
+        Err(VerifyError::BadJump {
+            offset: jump,
+            target: raw
+        })
+    );
+}
+
+#[test]
+pub fn misplaced_hints() {
+    let float = |x: f64| [OpCode::Float.address(), x.to_bits()];
+
+    // A `return` as an operand, and a loop as the value of an assignment.
+    let mut returned = vec![OpCode::Add.address(), Hint::Return.cell()];
+    returned.extend(float(1.0));
+    returned.extend(float(2.0));
+
+    let assigned = vec![
+        OpCode::Assign.address(),
+        0,
+        Hint::While.cell(),
+        OpCode::WhileLoop.address(),
+        8,
+        OpCode::False.address(),
+        OpCode::Block.address(),
+        8,
+    ];
+
+    for (tape, offset, hint) in [(&returned, 1, Hint::Return), (&assigned, 2, Hint::While)] {
+        assert_eq!(
+            verify(tape, 1),
+            Err(VerifyError::InvalidHint { offset, hint })
+        );
+        assert_eq!(
+            Node::decode(tape),
+            Err(DecodeError::InvalidHint { offset, hint })
+        );
+
+        let listing = Dissassembler::new(tape.clone(), vec!["x".into()]).listing();
+        assert!(listing
+            .lines
+            .iter()
+            .all(|line| !matches!(line.entry, Entry::Hint(_))));
+    }
+
+    // Hints can't start the program either.
+    assert_eq!(
+        verify(&assigned[2..], 0),
+        Err(VerifyError::InvalidHint {
+            offset: 0,
+            hint: Hint::While
+        })
+    );
+
+    let mut nested: Vec<u64> = [OpCode::Assign.address(), 0].repeat(Node::MAX_DEPTH + 1);
+    nested.push(OpCode::True.address());
+
+    assert_eq!(
+        verify(&nested, 1),
+        Err(VerifyError::TooDeep {
+            offset: 2 * Node::MAX_DEPTH
+        })
+    );
+    assert_eq!(
+        Node::decode(&nested),
+        Err(DecodeError::TooDeep {
+            offset: 2 * Node::MAX_DEPTH
+        })
+    );
+    assert_eq!(verify(&nested[4..], 1), Ok(()));
+}
+
+#[test]
+pub fn hint_cells() {
+    use crate::expr::*;
+
+    // Literals holding the bits of a hint, and of hints before they were
+    // tagged.
+    let program = Expr::Block(vec![
+        Binding::Global("x".into()).assign(Expr::Float(f64::from_bits(Hint::While.cell()))),
+        Expr::Return(Expr::Float(f64::from_bits(1001)).into()),
+    ]);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(program);
+
+    assert_eq!(compiler.verify(), Ok(()));
+
+    let mut tape = Vec::new();
+    Node::decode(&compiler.future_tape).unwrap().emit(&mut tape);
+    assert_eq!(tape, compiler.future_tape);
+
+    let hints: Vec<(usize, Hint)> = Dissassembler::from(compiler.clone())
+        .listing()
+        .lines
+        .into_iter()
+        .filter_map(|line| match line.entry {
+            Entry::Hint(hint) => Some((line.offset, hint)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(hints, vec![(6, Hint::Return)]);
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    assert_eq!(context.execute().as_float().map(f64::to_bits), Some(1001));
+
+    // Tagged cells that aren't hints aren't operations either.
+    let mut corrupted = compiler.future_tape.clone();
+    corrupted[6] = Hint::While.cell() + 1;
+
+    assert_eq!(
+        verify(&corrupted, 1),
+        Err(VerifyError::UnknownOp {
+            offset: 6,
+            cell: Hint::While.cell() + 1
+        })
+    );
+    assert_eq!(
+        Node::decode(&corrupted),
+        Err(DecodeError::UnknownOp {
+            offset: 6,
+            cell: Hint::While.cell() + 1
+        })
+    );
+}
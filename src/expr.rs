@@ -1,9 +1,9 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Binding {
     Global(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Float(f64),
     Boolean(bool),
@@ -13,12 +13,40 @@ pub enum Expr {
     Var(Binding),
     While(Box<Expr>, Box<Expr>),
     BinaryOp(Box<Expr>, Operator, Box<Expr>),
+    /// Makes the closest block whose value is used evaluate to a value,
+    /// unwinding the blocks, loops and conditionals used as statements in
+    /// between.
     Return(Box<Expr>),
-    // TODO: Make else body optional
-    Conditional(Box<(Expr, Expr)>, Vec<(Expr, Expr)>, Box<Expr>),
+    /// Hands a value to the host and suspends the program, evaluating to
+    /// the value it's resumed with.
+    Yield(Box<Expr>),
+    /// Creates a coroutine running the body on its first resume, every
+    /// `yield` it reaches suspends it instead of the program.
+    Coroutine(Box<Expr>),
+    /// Runs a coroutine until it yields or finishes, evaluating to the
+    /// yielded value or to its body's value. The second value is what the
+    /// `yield` it stopped at evaluates to.
+    Resume(Box<Expr>, Box<Expr>),
+    /// Whether a coroutine finished.
+    Done(Box<Expr>),
+    /// Unwinds the program up to the closest `Try`, runtime errors are
+    /// thrown as well.
+    Throw(Box<Expr>),
+    /// Evaluates to the value of `body`, or to the value of `handler` with
+    /// the exception bound to `catch_binding` if it threw. `finally` runs
+    /// after both, whether they threw or not.
+    Try {
+        body: Box<Expr>,
+        catch_binding: Binding,
+        handler: Box<Expr>,
+        finally: Option<Box<Expr>>,
+    },
+    /// Evaluates to the body of the first branch whose condition holds, or
+    /// to the `else` body, `Nil` without one.
+    Conditional(Box<(Expr, Expr)>, Vec<(Expr, Expr)>, Option<Box<Expr>>),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operator {
     Add,
     Sub,
@@ -33,6 +61,24 @@ pub enum Operator {
     Neq,
 }
 
+impl Operator {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Rem => "%",
+            Operator::Lt => "<",
+            Operator::Lte => "<=",
+            Operator::Gt => ">",
+            Operator::Gte => ">=",
+            Operator::Eq => "==",
+            Operator::Neq => "!=",
+        }
+    }
+}
+
 impl Binding {
     pub fn assign(self, value: impl Into<Box<Expr>>) -> Expr {
         Expr::Assign(self, value.into())
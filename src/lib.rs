@@ -1,4 +1,6 @@
 pub mod expr;
 pub mod compilers;
+pub mod passes;
+pub mod pretty;
 
 pub use compilers::*;
\ No newline at end of file
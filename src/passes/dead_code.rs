@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+
+use crate::expr::{Binding, Expr};
+use crate::passes::{check_types, Type, TypeInfo};
+
+/// Removes statements that can never run and stores to globals that are
+/// never read.
+///
+/// A statement is unreachable when it follows a `Return`, a `Throw`, or a `While`
+/// whose condition is a truthy literal (such a loop can only be left by
+/// returning). A store is dead when nothing reads the global afterwards,
+/// the host didn't list it in `observable`, and evaluating the stored value
+/// can neither throw nor have side effects. Reads that only feed stores to
+/// the same global (e.g. `x = x - 1`) don't keep it alive.
+pub fn eliminate_dead_code(expr: Expr, observable: &[&str]) -> Expr {
+    let mut expr = remove_unreachable(expr);
+
+    loop {
+        let types = check_types(&expr).ok();
+
+        let mut reads = HashSet::new();
+        collect_reads(&expr, None, types.as_ref(), &mut reads);
+
+        let mut removed = false;
+        expr = remove_dead_stores(expr, &reads, observable, types.as_ref(), &mut removed);
+
+        if !removed {
+            return expr;
+        }
+    }
+}
+
+fn remove_unreachable(expr: Expr) -> Expr {
+    match expr {
+        Expr::Block(statements) => {
+            let mut reachable = Vec::new();
+
+            for statement in statements {
+                let statement = remove_unreachable(statement);
+                let terminates = match &statement {
+                    Expr::Return(_) | Expr::Throw(_) => true,
+                    Expr::While(cond, _) => is_truthy_literal(cond),
+                    _ => false,
+                };
+
+                reachable.push(statement);
+
+                if terminates {
+                    break;
+                }
+            }
+
+            Expr::Block(reachable)
+        }
+
+        expr => map_children(expr, remove_unreachable),
+    }
+}
+
+fn remove_dead_stores(
+    expr: Expr,
+    reads: &HashSet<String>,
+    observable: &[&str],
+    types: Option<&TypeInfo>,
+    removed: &mut bool,
+) -> Expr {
+    match expr {
+        Expr::Block(statements) => {
+            let mut live = Vec::new();
+
+            for statement in statements {
+                if let Expr::Assign(Binding::Global(name), value) = &statement {
+                    if !reads.contains(name)
+                        && !observable.contains(&name.as_str())
+                        && is_pure(value, types)
+                    {
+                        *removed = true;
+                        continue;
+                    }
+                }
+
+                live.push(remove_dead_stores(
+                    statement, reads, observable, types, removed,
+                ));
+            }
+
+            Expr::Block(live)
+        }
+
+        expr => map_children(expr, |child| {
+            remove_dead_stores(child, reads, observable, types, removed)
+        }),
+    }
+}
+
+/// Collects every global read by `expr`, skipping reads of `ignore`.
+fn collect_reads(
+    expr: &Expr,
+    ignore: Option<&str>,
+    types: Option<&TypeInfo>,
+    reads: &mut HashSet<String>,
+) {
+    match expr {
+        Expr::Var(Binding::Global(name)) => {
+            if ignore != Some(name.as_str()) {
+                reads.insert(name.clone());
+            }
+        }
+
+        Expr::Assign(Binding::Global(name), value) => {
+            if is_pure(value, types) {
+                collect_reads(value, Some(name), types, reads);
+            } else {
+                collect_reads(value, ignore, types, reads);
+            }
+        }
+
+        Expr::Add(lhs, rhs)
+        | Expr::BinaryOp(lhs, _, rhs)
+        | Expr::While(lhs, rhs)
+        | Expr::Resume(lhs, rhs) => {
+            collect_reads(lhs, ignore, types, reads);
+            collect_reads(rhs, ignore, types, reads);
+        }
+
+        Expr::Block(statements) => {
+            for statement in statements {
+                collect_reads(statement, ignore, types, reads);
+            }
+        }
+
+        Expr::Return(value)
+        | Expr::Yield(value)
+        | Expr::Coroutine(value)
+        | Expr::Done(value)
+        | Expr::Throw(value) => collect_reads(value, ignore, types, reads),
+
+        Expr::Try {
+            body,
+            handler,
+            finally,
+            ..
+        } => {
+            collect_reads(body, ignore, types, reads);
+            collect_reads(handler, ignore, types, reads);
+
+            if let Some(finally) = finally {
+                collect_reads(finally, ignore, types, reads);
+            }
+        }
+
+        Expr::Conditional(first, elifs, else_body) => {
+            for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                collect_reads(cond, ignore, types, reads);
+                collect_reads(body, ignore, types, reads);
+            }
+
+            if let Some(else_body) = else_body {
+                collect_reads(else_body, ignore, types, reads);
+            }
+        }
+
+        Expr::Float(_) | Expr::Boolean(_) => {}
+    }
+}
+
+/// Whether evaluating `expr` can be skipped without changing anything.
+/// Without `types`, i.e. when the program doesn't type check, reads and
+/// operators are assumed to throw.
+fn is_pure(expr: &Expr, types: Option<&TypeInfo>) -> bool {
+    let Some(types) = types else {
+        return matches!(expr, Expr::Float(_) | Expr::Boolean(_));
+    };
+
+    match expr {
+        Expr::Float(_) | Expr::Boolean(_) => true,
+        Expr::Var(Binding::Global(name)) => !types.unassigned.contains(name),
+        // Operands of other types throw `InvalidOperands`.
+        Expr::Add(lhs, rhs) | Expr::BinaryOp(lhs, _, rhs) => {
+            is_pure(lhs, Some(types))
+                && is_pure(rhs, Some(types))
+                && types.type_of(lhs) == Type::Float
+                && types.type_of(rhs) == Type::Float
+        }
+        _ => false,
+    }
+}
+
+fn is_truthy_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Boolean(b) => *b,
+        Expr::Float(x) => *x == 1.0,
+        _ => false,
+    }
+}
+
+fn map_children(expr: Expr, mut f: impl FnMut(Expr) -> Expr) -> Expr {
+    match expr {
+        Expr::Assign(binding, value) => Expr::Assign(binding, f(*value).into()),
+        Expr::Add(lhs, rhs) => Expr::Add(f(*lhs).into(), f(*rhs).into()),
+        Expr::BinaryOp(lhs, op, rhs) => Expr::BinaryOp(f(*lhs).into(), op, f(*rhs).into()),
+        Expr::While(cond, body) => Expr::While(f(*cond).into(), f(*body).into()),
+        Expr::Return(value) => Expr::Return(f(*value).into()),
+        Expr::Yield(value) => Expr::Yield(f(*value).into()),
+        Expr::Coroutine(body) => Expr::Coroutine(f(*body).into()),
+        Expr::Resume(coroutine, value) => Expr::Resume(f(*coroutine).into(), f(*value).into()),
+        Expr::Done(coroutine) => Expr::Done(f(*coroutine).into()),
+        Expr::Throw(exception) => Expr::Throw(f(*exception).into()),
+        Expr::Try {
+            body,
+            catch_binding,
+            handler,
+            finally,
+        } => Expr::Try {
+            body: f(*body).into(),
+            catch_binding,
+            handler: f(*handler).into(),
+            finally: finally.map(|finally| f(*finally).into()),
+        },
+        Expr::Block(statements) => Expr::Block(statements.into_iter().map(f).collect()),
+        Expr::Conditional(first, elifs, else_body) => {
+            let (cond, body) = *first;
+            let first = (f(cond), f(body));
+            let elifs = elifs
+                .into_iter()
+                .map(|(cond, body)| (f(cond), f(body)))
+                .collect();
+
+            Expr::Conditional(
+                first.into(),
+                elifs,
+                else_body.map(|else_body| f(*else_body).into()),
+            )
+        }
+        expr @ (Expr::Float(_) | Expr::Boolean(_) | Expr::Var(_)) => expr,
+    }
+}
+
+#[test]
+pub fn unreachable_statements() {
+    let x = || Binding::Global("x".into());
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(1.0)),
+        Expr::While(
+            Expr::Boolean(true).into(),
+            Expr::Return(x().var().into()).into(),
+        ),
+        x().assign(Expr::Float(2.0)),
+        Expr::Return(Expr::Float(1.0).into()),
+        x().assign(Expr::Float(3.0)),
+    ]);
+
+    assert_eq!(
+        eliminate_dead_code(program, &[]),
+        Expr::Block(vec![
+            x().assign(Expr::Float(1.0)),
+            Expr::While(
+                Expr::Boolean(true).into(),
+                Expr::Return(x().var().into()).into(),
+            ),
+        ])
+    );
+}
+
+#[test]
+pub fn dead_stores() {
+    use crate::expr::Operator;
+
+    let global = |name: &str| Binding::Global(name.into());
+
+    let program = Expr::Block(vec![
+        global("a").assign(Expr::Float(1.0)),
+        global("b").assign(global("a").var().op(Operator::Add, Expr::Float(1.0))),
+        global("c").assign(Expr::Float(1.0)),
+        global("c").assign(global("c").var().op(Operator::Sub, Expr::Float(1.0))),
+        global("d").assign(Expr::Float(1.0)),
+        global("e").assign(Expr::Block(vec![global("d").assign(Expr::Float(2.0))])),
+    ]);
+
+    assert_eq!(
+        eliminate_dead_code(program, &["d"]),
+        Expr::Block(vec![
+            global("d").assign(Expr::Float(1.0)),
+            global("e").assign(Expr::Block(vec![global("d").assign(Expr::Float(2.0))])),
+        ])
+    );
+}
+
+#[test]
+pub fn throwing_stores() {
+    use crate::expr::Operator;
+    use crate::*;
+
+    let global = |name: &str| Binding::Global(name.into());
+
+    // `a` is never read, but storing it throws since `x` holds a boolean.
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(1.0)),
+        global("caught").assign(Expr::Float(0.0)),
+        Expr::Conditional(
+            (Expr::Boolean(true), global("x").assign(Expr::Boolean(true))).into(),
+            vec![],
+            None,
+        ),
+        Expr::Try {
+            body: global("a")
+                .assign(global("x").var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+            catch_binding: global("e"),
+            handler: global("caught").assign(Expr::Float(1.0)).into(),
+            finally: None,
+        },
+    ]);
+
+    assert!(passes::check_types(&program).is_ok());
+
+    let optimized = eliminate_dead_code(program.clone(), &["caught"]);
+    assert_eq!(optimized, program);
+
+    let mut compiler = ImCompiler::new();
+    compiler.compile_expr(optimized);
+
+    let mut context = CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+    context.execute();
+
+    assert_eq!(context.globals[1], Value::Float(1.0));
+
+    // Reads of globals that may not be assigned yet are kept as well.
+    let program = Expr::Block(vec![global("b").assign(global("c").var())]);
+    assert_eq!(eliminate_dead_code(program.clone(), &[]), program);
+}
@@ -0,0 +1,254 @@
+use crate::expr::{Expr, Operator};
+use crate::Value;
+
+/// Folds operations on literals into literals, prunes conditional
+/// branches whose condition is known at compile time and removes loops
+/// that never run.
+///
+/// Constants are evaluated with the same `Value` semantics the tape uses,
+/// so the folded program behaves exactly like the original one (NaNs,
+/// signed zeroes and infinities included). Operations that would fail at
+/// runtime (e.g. `true + 1.0`) are left untouched.
+pub fn fold_constants(expr: Expr) -> Expr {
+    match expr {
+        Expr::Assign(binding, value) => Expr::Assign(binding, fold_constants(*value).into()),
+        Expr::Add(lhs, rhs) => Expr::Add(fold_constants(*lhs).into(), fold_constants(*rhs).into()),
+        Expr::Return(value) => Expr::Return(fold_constants(*value).into()),
+        Expr::Yield(value) => Expr::Yield(fold_constants(*value).into()),
+        Expr::Coroutine(body) => Expr::Coroutine(fold_constants(*body).into()),
+        Expr::Resume(coroutine, value) => Expr::Resume(
+            fold_constants(*coroutine).into(),
+            fold_constants(*value).into(),
+        ),
+        Expr::Done(coroutine) => Expr::Done(fold_constants(*coroutine).into()),
+        Expr::Throw(exception) => Expr::Throw(fold_constants(*exception).into()),
+        Expr::Try {
+            body,
+            catch_binding,
+            handler,
+            finally,
+        } => Expr::Try {
+            body: fold_constants(*body).into(),
+            catch_binding,
+            handler: fold_constants(*handler).into(),
+            finally: finally.map(|finally| fold_constants(*finally).into()),
+        },
+
+        Expr::Block(statements) => Expr::Block(
+            statements
+                .into_iter()
+                .map(fold_constants)
+                // Loops that never run fold to empty blocks, which do nothing
+                .filter(|statement| !matches!(statement, Expr::Block(body) if body.is_empty()))
+                .collect(),
+        ),
+
+        Expr::While(cond, body) => {
+            let while_loop =
+                Expr::While(fold_constants(*cond).into(), fold_constants(*body).into());
+
+            if is_empty_loop(&while_loop) {
+                Expr::Block(vec![])
+            } else {
+                while_loop
+            }
+        }
+
+        Expr::BinaryOp(lhs, op, rhs) => {
+            let (lhs, rhs) = (fold_constants(*lhs), fold_constants(*rhs));
+
+            match (constant(&lhs), constant(&rhs)) {
+                (Some(l), Some(r)) => match apply(l, op, r) {
+                    Some(value) => literal(value),
+                    None => lhs.op(op, rhs),
+                },
+                _ => lhs.op(op, rhs),
+            }
+        }
+
+        Expr::Conditional(first, elifs, else_body) => {
+            let mut branches = Vec::new();
+            let mut else_body = else_body.map(|else_body| *else_body);
+
+            for (cond, body) in std::iter::once(*first).chain(elifs) {
+                let cond = fold_constants(cond);
+
+                match constant(&cond) {
+                    // Every branch after this one is unreachable
+                    Some(value) if value.truthy() => {
+                        else_body = Some(body);
+                        break;
+                    }
+                    Some(_) => {}
+                    None => branches.push((cond, fold_constants(body))),
+                }
+            }
+
+            let else_body = else_body.map(fold_constants);
+
+            if branches.is_empty() {
+                // Without an `else` body, nothing runs and it evaluates to `Nil`.
+                else_body.unwrap_or(Expr::Block(vec![]))
+            } else {
+                let first = branches.remove(0);
+                Expr::Conditional(first.into(), branches, else_body.map(Box::new))
+            }
+        }
+
+        expr @ (Expr::Float(_) | Expr::Boolean(_) | Expr::Var(_)) => expr,
+    }
+}
+
+fn is_empty_loop(expr: &Expr) -> bool {
+    match expr {
+        Expr::While(cond, _) => matches!(constant(cond), Some(value) if !value.truthy()),
+        _ => false,
+    }
+}
+
+fn constant(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Float(x) => Some(Value::Float(*x)),
+        Expr::Boolean(b) => Some(Value::Boolean(*b)),
+        _ => None,
+    }
+}
+
+fn literal(value: Value) -> Expr {
+    if let Some(x) = value.as_float() {
+        Expr::Float(x)
+    } else if let Some(b) = value.as_bool() {
+        Expr::Boolean(b)
+    } else {
+        unreachable!("Nil has no literal")
+    }
+}
+
+/// Mirrors `operations::impl_apply_op!`, returning `None` where
+/// the runtime would fail.
+fn apply(lhs: Value, op: Operator, rhs: Value) -> Option<Value> {
+    let arithmetic = |f: fn(f64, f64) -> f64| match (lhs.as_float(), rhs.as_float()) {
+        (Some(l), Some(r)) => Some(Value::Float(f(l, r))),
+        _ => None,
+    };
+
+    match op {
+        Operator::Add => arithmetic(|l, r| l + r),
+        Operator::Sub => arithmetic(|l, r| l - r),
+        Operator::Mul => arithmetic(|l, r| l * r),
+        Operator::Div => arithmetic(|l, r| l / r),
+        Operator::Rem => arithmetic(|l, r| l % r),
+        Operator::Eq => Some(Value::Boolean(lhs == rhs)),
+        Operator::Neq => Some(Value::Boolean(lhs != rhs)),
+        Operator::Gt => Some(Value::Boolean(lhs > rhs)),
+        Operator::Gte => Some(Value::Boolean(lhs >= rhs)),
+        Operator::Lt => Some(Value::Boolean(lhs < rhs)),
+        Operator::Lte => Some(Value::Boolean(lhs <= rhs)),
+    }
+}
+
+#[test]
+pub fn fold_arithmetic() {
+    let program = Expr::Float(2.0)
+        .op(Operator::Mul, Expr::Float(3.0))
+        .op(Operator::Add, Expr::Float(1.0));
+
+    assert_eq!(fold_constants(program), Expr::Float(7.0));
+
+    let program = Expr::Float(0.0).op(Operator::Div, Expr::Float(0.0));
+    assert!(matches!(fold_constants(program), Expr::Float(x) if x.is_nan()));
+
+    let program = Expr::Float(-0.0).op(Operator::Mul, Expr::Float(1.0));
+    assert!(matches!(fold_constants(program), Expr::Float(x) if x == 0.0 && x.is_sign_negative()));
+
+    let nan = Expr::Float(f64::NAN);
+    assert_eq!(
+        fold_constants(nan.clone().op(Operator::Eq, nan)),
+        Expr::Boolean(false)
+    );
+
+    // Would fail at runtime, so it's kept as is
+    let program = Expr::Boolean(true).op(Operator::Add, Expr::Float(1.0));
+    assert_eq!(fold_constants(program.clone()), program);
+
+    let program = Expr::global("x").op(Operator::Add, Expr::Float(1.0));
+    assert_eq!(fold_constants(program.clone()), program);
+}
+
+#[test]
+pub fn fold_conditional() {
+    let program = Expr::Conditional(
+        (Expr::Boolean(false), Expr::Float(10.0)).into(),
+        vec![
+            (Expr::global("x"), Expr::Float(9.0)),
+            (
+                Expr::Float(2.0).op(Operator::Gt, Expr::Float(1.0)),
+                Expr::Float(7.0),
+            ),
+            (Expr::global("y"), Expr::Float(3.0)),
+        ],
+        Some(Expr::Float(5.0).into()),
+    );
+
+    assert_eq!(
+        fold_constants(program),
+        Expr::Conditional(
+            (Expr::global("x"), Expr::Float(9.0)).into(),
+            vec![],
+            Some(Expr::Float(7.0).into())
+        )
+    );
+
+    let program = Expr::Conditional(
+        (Expr::Boolean(false), Expr::Float(10.0)).into(),
+        vec![(Expr::Float(0.0), Expr::Float(9.0))],
+        Some(Expr::Float(5.0).into()),
+    );
+
+    assert_eq!(fold_constants(program), Expr::Float(5.0));
+
+    let program = Expr::Conditional(
+        (Expr::Boolean(false), Expr::Float(10.0)).into(),
+        vec![],
+        None,
+    );
+
+    assert_eq!(fold_constants(program), Expr::Block(vec![]));
+}
+
+#[test]
+pub fn fold_while() {
+    let program = Expr::Block(vec![
+        Expr::While(
+            Expr::Boolean(false).into(),
+            crate::expr::Binding::Global("x".into())
+                .assign(Expr::Float(1.0))
+                .into(),
+        ),
+        Expr::Return(Expr::Float(1.0).op(Operator::Sub, Expr::Float(1.0)).into()),
+    ]);
+
+    let x = || crate::expr::Binding::Global("x".into());
+    let folded = fold_constants(Expr::Block(vec![
+        Expr::While(
+            Expr::Boolean(false).into(),
+            x().assign(Expr::Float(1.0)).into(),
+        ),
+        x().var(),
+    ]));
+
+    assert_eq!(folded, Expr::Block(vec![x().var()]));
+
+    let mut compiler = crate::ImCompiler::new();
+    compiler.compile_optimized(program, &[]);
+
+    assert!(compiler.globals.is_empty());
+
+    let mut context = crate::CallContext::new(
+        compiler.future_tape.as_ptr(),
+        compiler.future_tape.len(),
+        compiler.globals.len(),
+    );
+
+    assert_eq!(context.execute(), Value::Float(0.0));
+}
@@ -0,0 +1,16 @@
+pub mod folding;
+pub use folding::*;
+
+pub mod dead_code;
+pub use dead_code::*;
+
+pub mod types;
+pub use types::*;
+
+use crate::expr::Expr;
+
+/// Runs every optimization pass over `expr`, in order. Globals listed in
+/// `observable` are read by the host, so their stores are never removed.
+pub fn optimize(expr: Expr, observable: &[&str]) -> Expr {
+    eliminate_dead_code(fold_constants(expr), observable)
+}
@@ -0,0 +1,608 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::expr::{Binding, Expr, Operator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Nil,
+    Boolean,
+    Float,
+    Coroutine,
+    /// Holds values of different types depending on the path taken.
+    Any,
+}
+
+impl Type {
+    pub fn join(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Any
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    InvalidOperands {
+        operator: Operator,
+        lhs: Type,
+        rhs: Type,
+    },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::InvalidOperands { operator, lhs, rhs } => write!(
+                f,
+                "type mismatch: {operator:?} expects two Float operands, found {lhs} and {rhs}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// The types inferred for a program.
+#[derive(Debug, Clone, Default)]
+pub struct TypeInfo {
+    pub globals: HashMap<String, Type>,
+    /// Globals that may be read before they're assigned.
+    pub unassigned: HashSet<String>,
+}
+
+impl TypeInfo {
+    /// Type of a global, `Any` if it may be read before it's assigned.
+    pub fn global(&self, name: &str) -> Type {
+        if self.unassigned.contains(name) {
+            return Type::Any;
+        }
+
+        self.globals.get(name).copied().unwrap_or(Type::Float)
+    }
+
+    /// Infers the type of `expr`, which must be part of the checked program.
+    pub fn type_of(&self, expr: &Expr) -> Type {
+        let pending = HashSet::new();
+        let mut checker = Checker::new(self, &pending);
+
+        checker.infer(expr).unwrap_or(Type::Nil)
+    }
+}
+
+/// Infers the type of every global and expression of `program`, reporting
+/// every operation that would fail at runtime because of its operand types.
+///
+/// Globals get the union of every type assigned to them, or `Any` when
+/// they may be read before they're assigned.
+pub fn check_types(program: &Expr) -> Result<TypeInfo, Vec<TypeError>> {
+    let mut info = TypeInfo::default();
+    collect_unassigned(program, &mut HashSet::new(), &mut info.unassigned);
+
+    let mut pending = HashSet::new();
+    collect_assigned(program, &mut pending);
+
+    // Assigning a global can change the type of other globals, iterate
+    // until nothing changes. Types only ever widen so this terminates.
+    loop {
+        let mut checker = Checker::new(&info, &pending);
+        checker.infer(program);
+
+        let mut globals = info.globals.clone();
+
+        for (name, ty) in checker.assigned {
+            let entry = globals.entry(name).or_insert(ty);
+            *entry = entry.join(ty);
+        }
+
+        if globals == info.globals {
+            break;
+        }
+
+        info.globals = globals;
+    }
+
+    let mut errors = Vec::new();
+    let mut checker = Checker::new(&info, &pending);
+    checker.errors = Some(&mut errors);
+    checker.infer(program);
+
+    if errors.is_empty() {
+        Ok(info)
+    } else {
+        Err(errors)
+    }
+}
+
+fn collect_assigned(expr: &Expr, assigned: &mut HashSet<String>) {
+    match expr {
+        Expr::Assign(Binding::Global(name), value) => {
+            assigned.insert(name.clone());
+            collect_assigned(value, assigned);
+        }
+        Expr::Add(lhs, rhs)
+        | Expr::BinaryOp(lhs, _, rhs)
+        | Expr::While(lhs, rhs)
+        | Expr::Resume(lhs, rhs) => {
+            collect_assigned(lhs, assigned);
+            collect_assigned(rhs, assigned);
+        }
+        Expr::Block(statements) => {
+            for statement in statements {
+                collect_assigned(statement, assigned);
+            }
+        }
+        Expr::Return(value)
+        | Expr::Yield(value)
+        | Expr::Coroutine(value)
+        | Expr::Done(value)
+        | Expr::Throw(value) => collect_assigned(value, assigned),
+        Expr::Try {
+            body,
+            catch_binding: Binding::Global(name),
+            handler,
+            finally,
+        } => {
+            assigned.insert(name.clone());
+            collect_assigned(body, assigned);
+            collect_assigned(handler, assigned);
+
+            if let Some(finally) = finally {
+                collect_assigned(finally, assigned);
+            }
+        }
+        Expr::Conditional(first, elifs, else_body) => {
+            for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                collect_assigned(cond, assigned);
+                collect_assigned(body, assigned);
+            }
+
+            if let Some(else_body) = else_body {
+                collect_assigned(else_body, assigned);
+            }
+        }
+        Expr::Float(_) | Expr::Boolean(_) | Expr::Var(_) => {}
+    }
+}
+
+/// Collects the globals read while they may not be assigned yet, following
+/// the order the program runs in. `defined` holds the globals assigned on
+/// every path so far.
+fn collect_unassigned(
+    expr: &Expr,
+    defined: &mut HashSet<String>,
+    unassigned: &mut HashSet<String>,
+) {
+    match expr {
+        Expr::Var(Binding::Global(name)) => {
+            if !defined.contains(name) {
+                unassigned.insert(name.clone());
+            }
+        }
+        Expr::Assign(Binding::Global(name), value) => {
+            collect_unassigned(value, defined, unassigned);
+            defined.insert(name.clone());
+        }
+        Expr::Add(lhs, rhs) | Expr::BinaryOp(lhs, _, rhs) | Expr::Resume(lhs, rhs) => {
+            collect_unassigned(lhs, defined, unassigned);
+            collect_unassigned(rhs, defined, unassigned);
+        }
+        Expr::Block(statements) => {
+            for statement in statements {
+                collect_unassigned(statement, defined, unassigned);
+
+                if let Expr::Return(_) | Expr::Throw(_) = statement {
+                    break;
+                }
+            }
+        }
+        Expr::Return(value) | Expr::Yield(value) | Expr::Done(value) | Expr::Throw(value) => {
+            collect_unassigned(value, defined, unassigned)
+        }
+        // The body runs later on, or not at all.
+        Expr::Coroutine(body) => collect_unassigned(body, &mut defined.clone(), unassigned),
+        Expr::While(cond, body) => {
+            collect_unassigned(cond, defined, unassigned);
+            collect_unassigned(body, &mut defined.clone(), unassigned);
+        }
+        Expr::Conditional(first, elifs, else_body) => {
+            let mut conds = defined.clone();
+            let mut paths = Vec::new();
+
+            for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                collect_unassigned(cond, &mut conds, unassigned);
+
+                let mut path = conds.clone();
+                collect_unassigned(body, &mut path, unassigned);
+                paths.push(path);
+            }
+
+            if let Some(else_body) = else_body {
+                collect_unassigned(else_body, &mut conds, unassigned);
+            }
+            paths.push(conds);
+
+            *defined = intersection(paths);
+        }
+        // The body can throw before assigning anything, the handler only
+        // runs if it did.
+        Expr::Try {
+            body,
+            catch_binding: Binding::Global(name),
+            handler,
+            finally,
+        } => {
+            let mut completed = defined.clone();
+            collect_unassigned(body, &mut completed, unassigned);
+
+            let mut caught = defined.clone();
+            caught.insert(name.clone());
+            collect_unassigned(handler, &mut caught, unassigned);
+
+            *defined = intersection(vec![completed, caught]);
+
+            if let Some(finally) = finally {
+                collect_unassigned(finally, defined, unassigned);
+            }
+        }
+        Expr::Float(_) | Expr::Boolean(_) => {}
+    }
+}
+
+fn intersection(mut sets: Vec<HashSet<String>>) -> HashSet<String> {
+    let mut result = sets.pop().unwrap_or_default();
+
+    for set in sets {
+        result.retain(|name| set.contains(name));
+    }
+
+    result
+}
+
+struct Checker<'a> {
+    info: &'a TypeInfo,
+    /// Globals assigned somewhere in the program.
+    pending: &'a HashSet<String>,
+    assigned: HashMap<String, Type>,
+    /// Types returned to each enclosing block whose value is used.
+    returns: Vec<Option<Type>>,
+    errors: Option<&'a mut Vec<TypeError>>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(info: &'a TypeInfo, pending: &'a HashSet<String>) -> Self {
+        Self {
+            info,
+            pending,
+            assigned: HashMap::new(),
+            returns: Vec::new(),
+            errors: None,
+        }
+    }
+
+    /// Returns `None` for expressions whose type isn't known yet, i.e.
+    /// globals that are only assigned later on in the program.
+    fn infer(&mut self, expr: &Expr) -> Option<Type> {
+        match expr {
+            Expr::Float(_) => Some(Type::Float),
+            Expr::Boolean(_) => Some(Type::Boolean),
+
+            Expr::Var(Binding::Global(name)) => {
+                if self.pending.contains(name)
+                    && !self.info.globals.contains_key(name)
+                    && !self.info.unassigned.contains(name)
+                {
+                    None
+                } else {
+                    Some(self.info.global(name))
+                }
+            }
+
+            Expr::Assign(Binding::Global(name), value) => {
+                if let Some(ty) = self.infer(value) {
+                    let entry = self.assigned.entry(name.clone()).or_insert(ty);
+                    *entry = entry.join(ty);
+                }
+
+                Some(Type::Nil)
+            }
+
+            Expr::Add(lhs, rhs) => self.binary_op(lhs, Operator::Add, rhs),
+            Expr::BinaryOp(lhs, op, rhs) => self.binary_op(lhs, *op, rhs),
+
+            Expr::Block(statements) => {
+                self.returns.push(None);
+                let mut completes = true;
+
+                for statement in statements {
+                    self.statement(statement);
+
+                    if let Expr::Return(_) = statement {
+                        completes = false;
+                        break;
+                    }
+                }
+
+                let returned = self.returns.pop().flatten();
+
+                match (returned, completes) {
+                    (Some(ty), true) => Some(ty.join(Type::Nil)),
+                    (Some(ty), false) => Some(ty),
+                    (None, _) => Some(Type::Nil),
+                }
+            }
+
+            Expr::Return(value) => {
+                let ty = self.infer(value);
+
+                if let (Some(ty), Some(returned)) = (ty, self.returns.last_mut()) {
+                    *returned = Some(returned.map_or(ty, |returned| returned.join(ty)));
+                }
+
+                Some(Type::Nil)
+            }
+
+            // The host can resume the program with any value.
+            Expr::Yield(value) => {
+                self.infer(value);
+
+                Some(Type::Any)
+            }
+
+            // The body returns to the coroutine, not to the enclosing block.
+            Expr::Coroutine(body) => {
+                self.returns.push(None);
+                self.infer(body);
+                self.returns.pop();
+
+                Some(Type::Coroutine)
+            }
+
+            // Yields and the body can evaluate to anything.
+            Expr::Resume(coroutine, value) => {
+                self.infer(coroutine);
+                self.infer(value);
+
+                Some(Type::Any)
+            }
+
+            Expr::Done(coroutine) => {
+                self.infer(coroutine);
+
+                Some(Type::Boolean)
+            }
+
+            Expr::Throw(exception) => {
+                self.infer(exception);
+
+                Some(Type::Nil)
+            }
+
+            // Anything can be thrown, runtime errors included.
+            Expr::Try {
+                body,
+                catch_binding: Binding::Global(name),
+                handler,
+                finally,
+            } => {
+                let body = self.infer(body);
+
+                let entry = self.assigned.entry(name.clone()).or_insert(Type::Any);
+                *entry = entry.join(Type::Any);
+
+                let handler = self.infer(handler);
+
+                if let Some(finally) = finally {
+                    self.infer(finally);
+                }
+
+                match (body, handler) {
+                    (Some(lhs), Some(rhs)) => Some(lhs.join(rhs)),
+                    (lhs, rhs) => lhs.or(rhs),
+                }
+            }
+
+            Expr::While(cond, body) => {
+                self.infer(cond);
+                self.statement(body);
+
+                Some(Type::Nil)
+            }
+
+            Expr::Conditional(first, elifs, else_body) => {
+                let mut ty = match else_body {
+                    Some(else_body) => self.infer(else_body),
+                    None => Some(Type::Nil),
+                };
+
+                for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                    self.infer(cond);
+
+                    ty = match (ty, self.infer(body)) {
+                        (Some(lhs), Some(rhs)) => Some(lhs.join(rhs)),
+                        (lhs, rhs) => lhs.or(rhs),
+                    };
+                }
+
+                ty
+            }
+        }
+    }
+
+    /// Infers a statement of a block, loop or conditional, the blocks and
+    /// conditionals used as statements return to the enclosing block.
+    fn statement(&mut self, statement: &Expr) {
+        match statement {
+            Expr::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement);
+
+                    if let Expr::Return(_) = statement {
+                        break;
+                    }
+                }
+            }
+
+            Expr::Conditional(first, elifs, else_body) => {
+                for (cond, body) in std::iter::once(&**first).chain(elifs) {
+                    self.infer(cond);
+                    self.statement(body);
+                }
+
+                if let Some(else_body) = else_body {
+                    self.statement(else_body);
+                }
+            }
+
+            statement => {
+                self.infer(statement);
+            }
+        }
+    }
+
+    fn binary_op(&mut self, lhs: &Expr, operator: Operator, rhs: &Expr) -> Option<Type> {
+        let (lhs, rhs) = (self.infer(lhs), self.infer(rhs));
+
+        match operator {
+            Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Rem => {
+                let numeric = |ty: Option<Type>| matches!(ty, None | Some(Type::Float | Type::Any));
+
+                if !numeric(lhs) || !numeric(rhs) {
+                    if let Some(errors) = &mut self.errors {
+                        errors.push(TypeError::InvalidOperands {
+                            operator,
+                            lhs: lhs.unwrap_or(Type::Any),
+                            rhs: rhs.unwrap_or(Type::Any),
+                        });
+                    }
+                }
+
+                Some(Type::Float)
+            }
+
+            Operator::Eq
+            | Operator::Neq
+            | Operator::Gt
+            | Operator::Gte
+            | Operator::Lt
+            | Operator::Lte => Some(Type::Boolean),
+        }
+    }
+}
+
+#[test]
+pub fn infer_types() {
+    let global = |name: &str| Binding::Global(name.into());
+
+    let program = Expr::Block(vec![
+        global("x").assign(Expr::Float(10.0)),
+        global("flag").assign(global("x").var().op(Operator::Gt, Expr::Float(0.0))),
+        global("y").assign(global("z").var()),
+        global("z").assign(Expr::Float(1.0)),
+        global("w").assign(Expr::Boolean(true)),
+        global("w").assign(Expr::Float(1.0)),
+        Expr::Return(global("x").var().op(Operator::Mul, Expr::Float(2.0)).into()),
+    ]);
+
+    let info = check_types(&program).unwrap();
+
+    assert_eq!(info.global("x"), Type::Float);
+    assert_eq!(info.global("flag"), Type::Boolean);
+    assert_eq!(info.global("y"), Type::Any);
+    assert_eq!(info.global("z"), Type::Any);
+    assert_eq!(info.global("w"), Type::Any);
+    assert_eq!(info.type_of(&program), Type::Float);
+    assert_eq!(info.type_of(&Expr::Block(vec![])), Type::Nil);
+
+    // Only globals assigned on every path are assigned afterwards.
+    let program = Expr::Block(vec![
+        Expr::Conditional(
+            (Expr::Boolean(true), global("a").assign(Expr::Float(1.0))).into(),
+            vec![],
+            None,
+        ),
+        Expr::Try {
+            body: global("b").assign(Expr::Float(1.0)).into(),
+            catch_binding: global("e"),
+            handler: global("b").assign(Expr::Float(2.0)).into(),
+            finally: None,
+        },
+        global("c").assign(
+            global("a")
+                .var()
+                .op(Operator::Add, global("b").var())
+                .op(Operator::Add, global("e").var()),
+        ),
+    ]);
+
+    let info = check_types(&program).unwrap();
+
+    assert_eq!(
+        info.unassigned,
+        HashSet::from(["a".to_string(), "e".to_string()])
+    );
+    assert_eq!(info.global("b"), Type::Float);
+
+    // Returns from the loop and the conditional it's in.
+    let nested = Expr::Block(vec![
+        Expr::While(
+            Expr::Boolean(true).into(),
+            Expr::Block(vec![Expr::Conditional(
+                (
+                    global("flag").var(),
+                    Expr::Return(Expr::Boolean(true).into()),
+                )
+                    .into(),
+                vec![],
+                None,
+            )])
+            .into(),
+        ),
+        Expr::Return(Expr::Float(1.0).into()),
+    ]);
+
+    assert_eq!(info.type_of(&nested), Type::Any);
+}
+
+#[test]
+pub fn report_mismatches() {
+    let program = Expr::Block(vec![
+        Binding::Global("flag".into()).assign(Expr::Boolean(true)),
+        Expr::Return(
+            Expr::Boolean(true)
+                .op(Operator::Add, Expr::Float(1.0))
+                .op(Operator::Sub, Expr::global("flag"))
+                .into(),
+        ),
+    ]);
+
+    let errors = check_types(&program).unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![
+            TypeError::InvalidOperands {
+                operator: Operator::Add,
+                lhs: Type::Boolean,
+                rhs: Type::Float,
+            },
+            TypeError::InvalidOperands {
+                operator: Operator::Sub,
+                lhs: Type::Float,
+                rhs: Type::Boolean,
+            },
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "type mismatch: Add expects two Float operands, found Boolean and Float"
+    );
+}
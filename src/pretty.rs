@@ -0,0 +1,302 @@
+//! Renders expressions as source code through their `Display` impl.
+//!
+//! Blocks are indented by four spaces and binary operations only get the
+//! parentheses needed to keep their meaning: `*`, `/` and `%` bind tighter
+//! than `+` and `-`, which bind tighter than comparisons. Operators of the
+//! same precedence associate to the left, and comparisons don't chain.
+
+use std::fmt;
+
+use crate::expr::{Binding, Expr, Operator};
+
+const INDENT: usize = 4;
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Global(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl Operator {
+    /// How tightly the operator binds its operands, higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Operator::Mul | Operator::Div | Operator::Rem => 3,
+            Operator::Add | Operator::Sub => 2,
+            Operator::Lt
+            | Operator::Lte
+            | Operator::Gt
+            | Operator::Gte
+            | Operator::Eq
+            | Operator::Neq => 1,
+        }
+    }
+}
+
+/// Precedence of an expression used as an operand, assignments and
+/// control flow extend as far right as possible so they always need
+/// parentheses.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BinaryOp(_, op, _) => op.precedence(),
+        Expr::Add(_, _) => Operator::Add.precedence(),
+        Expr::Assign(_, _)
+        | Expr::Return(_)
+        | Expr::Yield(_)
+        | Expr::Throw(_)
+        | Expr::While(_, _)
+        | Expr::Conditional(..) => 0,
+        Expr::Float(_)
+        | Expr::Boolean(_)
+        | Expr::Var(_)
+        | Expr::Block(_)
+        | Expr::Coroutine(_)
+        | Expr::Resume(_, _)
+        | Expr::Done(_)
+        | Expr::Try { .. } => u8::MAX,
+    }
+}
+
+fn write_expr(f: &mut fmt::Formatter<'_>, expr: &Expr, indent: usize) -> fmt::Result {
+    match expr {
+        Expr::Float(x) => write!(f, "{x:?}"),
+        Expr::Boolean(b) => write!(f, "{b}"),
+        Expr::Var(binding) => write!(f, "{binding}"),
+        Expr::Assign(binding, value) => {
+            write!(f, "{binding} = ")?;
+            write_expr(f, value, indent)
+        }
+        Expr::Return(value) => {
+            write!(f, "return ")?;
+            write_expr(f, value, indent)
+        }
+        Expr::Yield(value) => {
+            write!(f, "yield ")?;
+            write_expr(f, value, indent)
+        }
+        Expr::Coroutine(body) => {
+            write!(f, "coroutine ")?;
+            write_body(f, body, indent)
+        }
+        Expr::Resume(coroutine, value) => {
+            write!(f, "resume(")?;
+            write_expr(f, coroutine, indent)?;
+            write!(f, ", ")?;
+            write_expr(f, value, indent)?;
+            write!(f, ")")
+        }
+        Expr::Done(coroutine) => {
+            write!(f, "done(")?;
+            write_expr(f, coroutine, indent)?;
+            write!(f, ")")
+        }
+        Expr::Throw(exception) => {
+            write!(f, "throw ")?;
+            write_expr(f, exception, indent)
+        }
+        Expr::Try {
+            body,
+            catch_binding,
+            handler,
+            finally,
+        } => {
+            write!(f, "try ")?;
+            write_body(f, body, indent)?;
+            write!(f, " catch {catch_binding} ")?;
+            write_body(f, handler, indent)?;
+
+            if let Some(finally) = finally {
+                write!(f, " finally ")?;
+                write_body(f, finally, indent)?;
+            }
+
+            Ok(())
+        }
+        Expr::Block(statements) if statements.is_empty() => write!(f, "{{}}"),
+        Expr::Block(statements) => {
+            writeln!(f, "{{")?;
+
+            for statement in statements {
+                write!(f, "{:indent$}", "", indent = indent + INDENT)?;
+                write_expr(f, statement, indent + INDENT)?;
+                writeln!(f)?;
+            }
+
+            write!(f, "{:indent$}}}", "")
+        }
+        Expr::While(cond, body) => {
+            write!(f, "while ")?;
+            write_expr(f, cond, indent)?;
+            write!(f, " ")?;
+            write_body(f, body, indent)
+        }
+        Expr::Conditional(first, elifs, else_body) => {
+            for (i, (cond, body)) in std::iter::once(&**first).chain(elifs).enumerate() {
+                write!(f, "{}", if i == 0 { "if " } else { " else if " })?;
+                write_expr(f, cond, indent)?;
+                write!(f, " ")?;
+                write_body(f, body, indent)?;
+            }
+
+            if let Some(else_body) = else_body {
+                write!(f, " else ")?;
+                write_body(f, else_body, indent)?;
+            }
+
+            Ok(())
+        }
+        Expr::Add(lhs, rhs) => write_binary_op(f, lhs, Operator::Add, rhs, indent),
+        Expr::BinaryOp(lhs, op, rhs) => write_binary_op(f, lhs, *op, rhs, indent),
+    }
+}
+
+/// Bodies of loops, branches, coroutines and `try`s are always written as
+/// blocks.
+fn write_body(f: &mut fmt::Formatter<'_>, body: &Expr, indent: usize) -> fmt::Result {
+    match body {
+        Expr::Block(_) => write_expr(f, body, indent),
+        body => write_expr(f, &Expr::Block(vec![body.clone()]), indent),
+    }
+}
+
+fn write_binary_op(
+    f: &mut fmt::Formatter<'_>,
+    lhs: &Expr,
+    op: Operator,
+    rhs: &Expr,
+    indent: usize,
+) -> fmt::Result {
+    let comparison = op.precedence() == Operator::Eq.precedence();
+
+    let lhs_parens =
+        precedence(lhs) < op.precedence() || (comparison && precedence(lhs) == op.precedence());
+    let rhs_parens = precedence(rhs) <= op.precedence();
+
+    write_operand(f, lhs, lhs_parens, indent)?;
+    write!(f, " {op} ")?;
+    write_operand(f, rhs, rhs_parens, indent)
+}
+
+fn write_operand(
+    f: &mut fmt::Formatter<'_>,
+    operand: &Expr,
+    parens: bool,
+    indent: usize,
+) -> fmt::Result {
+    if parens {
+        write!(f, "(")?;
+        write_expr(f, operand, indent)?;
+        write!(f, ")")
+    } else {
+        write_expr(f, operand, indent)
+    }
+}
+
+#[test]
+pub fn pretty_print() {
+    let var = |name: &str| Expr::global(name);
+    let x = || Binding::Global("x".into());
+
+    for (expr, source) in [
+        (
+            var("a")
+                .op(Operator::Add, var("b"))
+                .op(Operator::Mul, var("c")),
+            "(a + b) * c",
+        ),
+        (
+            var("a").op(Operator::Add, var("b").op(Operator::Mul, var("c"))),
+            "a + b * c",
+        ),
+        (
+            var("a")
+                .op(Operator::Sub, var("b"))
+                .op(Operator::Sub, var("c")),
+            "a - b - c",
+        ),
+        (
+            var("a").op(Operator::Sub, var("b").op(Operator::Sub, var("c"))),
+            "a - (b - c)",
+        ),
+        (
+            var("a")
+                .op(Operator::Lt, var("b"))
+                .op(Operator::Eq, Expr::Boolean(true)),
+            "(a < b) == true",
+        ),
+        (
+            x().assign(Expr::Float(1.0))
+                .op(Operator::Add, Expr::Float(2.5)),
+            "(x = 1.0) + 2.5",
+        ),
+        (
+            x().assign(var("a").op(Operator::Rem, var("b"))),
+            "x = a % b",
+        ),
+        (
+            x().assign(x().var().op(Operator::Add, Expr::Yield(var("a").into()))),
+            "x = x + (yield a)",
+        ),
+        (
+            Expr::Resume(var("g").into(), Expr::Float(1.0).into())
+                .op(Operator::Add, Expr::Done(var("g").into())),
+            "resume(g, 1.0) + done(g)",
+        ),
+    ] {
+        assert_eq!(expr.to_string(), source);
+    }
+
+    let program = Expr::Block(vec![
+        x().assign(Expr::Float(0.0)),
+        Expr::While(
+            x().var().op(Operator::Lt, Expr::Float(10.0)).into(),
+            x().assign(x().var().op(Operator::Add, Expr::Float(1.0)))
+                .into(),
+        ),
+        Expr::Conditional(
+            (var("flag"), Expr::Return(var("x").into())).into(),
+            vec![(
+                x().var().op(Operator::Gt, Expr::Float(5.0)),
+                Expr::Block(vec![]),
+            )],
+            Some(Expr::Block(vec![Expr::Return(Expr::Boolean(false).into())]).into()),
+        ),
+        Expr::Conditional(
+            (var("flag"), x().assign(Expr::Float(1.0))).into(),
+            vec![],
+            None,
+        ),
+    ]);
+
+    assert_eq!(
+        program.to_string(),
+        "{
+    x = 0.0
+    while x < 10.0 {
+        x = x + 1.0
+    }
+    if flag {
+        return x
+    } else if x > 5.0 {} else {
+        return false
+    }
+    if flag {
+        x = 1.0
+    }
+}"
+    );
+}